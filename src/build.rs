@@ -0,0 +1,200 @@
+use crate::json::Json;
+use crate::lsp::Severity;
+
+/// The build command used when none is configured
+pub const DEFAULT_BUILD_COMMAND: &str = "cargo build";
+
+/// The cargo commands that report the compiler messages as JSON with
+/// `--message-format=json`
+const CARGO_JSON_COMMANDS: [&str; 5] = ["build", "check", "clippy", "test", "bench"];
+
+/// An error or a warning found in the output of a build
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Problem {
+    pub file: String,
+    /// The line, counting from 1
+    pub line: usize,
+    /// The column, counting characters from 1
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Returns the command line actually run for `command`: cargo commands
+/// that can describe their messages as JSON are asked to.
+#[must_use]
+pub fn command_line(command: &str) -> String {
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("cargo"), Some(subcommand))
+            if CARGO_JSON_COMMANDS.contains(&subcommand) && !command.contains("--message-format") =>
+        {
+            let rest: Vec<&str> = words.collect();
+            format!("cargo {subcommand} --message-format=json {}", rest.join(" "))
+                .trim_end()
+                .to_owned()
+        }
+        _ => command.to_owned(),
+    }
+}
+
+/// Finds the problems in the output of a build, one line at a time. Both
+/// the JSON messages of cargo and `file:line:column` locations are
+/// understood; a location on its own, as in `--> src/main.rs:3:5`, gets
+/// the message of the `error:` or `warning:` line before it.
+#[derive(Default)]
+pub struct Parser {
+    message: Option<(Severity, String)>,
+}
+
+impl Parser {
+    /// Returns the problem reported on `line`, if there is one
+    pub fn parse_line(&mut self, line: &str) -> Option<Problem> {
+        let trimmed = line.trim();
+        if trimmed.starts_with('{') {
+            return Json::parse(trimmed).ok().as_ref().and_then(cargo_message);
+        }
+        if let Some((severity, message)) = message_line(trimmed) {
+            self.message = Some((severity, message.to_owned()));
+            return None;
+        }
+        let (location, arrow) = match trimmed.strip_prefix("-->") {
+            Some(location) => (location.trim_start(), true),
+            None => (trimmed, false),
+        };
+        let (file, line, column, rest) = parse_location(location)?;
+        let (severity, message) = if let Some((severity, message)) = message_line(rest) {
+            (severity, message.to_owned())
+        } else if !rest.is_empty() {
+            (Severity::Error, rest.to_owned())
+        } else if arrow {
+            self.message.take()?
+        } else {
+            (Severity::Error, String::new())
+        };
+        Some(Problem {
+            file: file.to_owned(),
+            line,
+            column,
+            severity,
+            message,
+        })
+    }
+}
+
+/// Parses a compiler message of cargo, located at its primary span
+fn cargo_message(value: &Json) -> Option<Problem> {
+    if value.get("reason")?.as_str()? != "compiler-message" {
+        return None;
+    }
+    let message = value.get("message")?;
+    let severity = match message.get("level")?.as_str()? {
+        "warning" => Severity::Warning,
+        "note" | "help" => Severity::Information,
+        _ => Severity::Error,
+    };
+    let span = message
+        .get("spans")?
+        .as_array()?
+        .iter()
+        .find(|span| span.get("is_primary") == Some(&Json::Bool(true)))?;
+    Some(Problem {
+        file: span.get("file_name")?.as_str()?.to_owned(),
+        line: span.get("line_start")?.as_usize()?,
+        column: span.get("column_start")?.as_usize()?,
+        severity,
+        message: message.get("message")?.as_str()?.to_owned(),
+    })
+}
+
+/// Parses a line starting with `error` or `warning`, optionally followed
+/// by a code in brackets, then `:` and the message
+fn message_line(line: &str) -> Option<(Severity, &str)> {
+    let (severity, rest) = if let Some(rest) = line.strip_prefix("error") {
+        (Severity::Error, rest)
+    } else if let Some(rest) = line.strip_prefix("warning") {
+        (Severity::Warning, rest)
+    } else {
+        return None;
+    };
+    let rest = if rest.starts_with('[') {
+        rest.split_once(']')?.1
+    } else {
+        rest
+    };
+    Some((severity, rest.strip_prefix(':')?.trim()))
+}
+
+/// Parses `file:line:column` or `file:line` at the start of `text`, and
+/// returns them with the rest of the text after a `:`
+fn parse_location(text: &str) -> Option<(&str, usize, usize, &str)> {
+    let mut parts = text.splitn(4, ':');
+    let file = parts.next()?;
+    let looks_like_path = !file.is_empty()
+        && !file.contains(char::is_whitespace)
+        && (file.contains('.') || file.contains('/'));
+    if !looks_like_path {
+        return None;
+    }
+    let line = parts.next()?.parse().ok().filter(|&line| line > 0)?;
+    let (column, rest) = match parts.next() {
+        Some(column) => match column.parse() {
+            Ok(column) => (column, parts.next().unwrap_or_default()),
+            Err(_) => (1, text.splitn(3, ':').nth(2).unwrap_or_default()),
+        },
+        None => (1, ""),
+    };
+    Some((file, line, column, rest.trim()))
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    fn problem(file: &str, line: usize, column: usize, severity: Severity, message: &str) -> Problem {
+        Problem {
+            file: file.to_owned(),
+            line,
+            column,
+            severity,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!(command_line("cargo check"), "cargo check --message-format=json");
+        assert_eq!(
+            command_line("cargo test -- --nocapture"),
+            "cargo test --message-format=json -- --nocapture"
+        );
+        assert_eq!(command_line("cargo run"), "cargo run");
+        assert_eq!(command_line("make -j4"), "make -j4");
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let mut parser = Parser::default();
+        let lines = [
+            "   Compiling hammare v0.1.0",
+            "error[E0425]: cannot find value `x` in this scope",
+            "  --> src/main.rs:3:5",
+            "   |",
+            "main.c:10:2: warning: unused variable 'y' [-Wunused-variable]",
+            "src/lib.py:7: undefined name",
+            "12:30:45 build started",
+            r#"{"reason":"compiler-message","message":{"level":"warning","message":"unused import","spans":[{"file_name":"src/a.rs","line_start":1,"column_start":5,"is_primary":true}]}}"#,
+            r#"{"reason":"build-finished","success":false}"#,
+        ];
+        let problems: Vec<Problem> = lines.iter().filter_map(|line| parser.parse_line(line)).collect();
+        assert_eq!(
+            problems,
+            [
+                problem("src/main.rs", 3, 5, Severity::Error, "cannot find value `x` in this scope"),
+                problem("main.c", 10, 2, Severity::Warning, "unused variable 'y' [-Wunused-variable]"),
+                problem("src/lib.py", 7, 1, Severity::Error, "undefined name"),
+                problem("src/a.rs", 1, 5, Severity::Warning, "unused import"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Position;
+
+/// An ongoing completion of the word before the cursor, cycling through
+/// the words of the document that start the same way
+pub struct Completion {
+    /// Where the completed word starts
+    start: Position,
+    /// The part of the word typed before completing
+    prefix: String,
+    candidates: Vec<String>,
+    /// The candidate currently inserted, or `None` while the typed prefix
+    /// is shown, after cycling past the last or the first candidate
+    selected: Option<usize>,
+}
+
+impl Completion {
+    /// Starts a completion of `prefix`, typed at `start`, with the
+    /// candidates ranked best first. Returns `None` without candidates.
+    #[must_use]
+    pub fn new(start: Position, prefix: String, candidates: Vec<String>) -> Option<Self> {
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Self {
+            start,
+            prefix,
+            candidates,
+            selected: None,
+        })
+    }
+
+    /// Returns where the completed word starts
+    #[must_use]
+    pub fn start(&self) -> &Position {
+        &self.start
+    }
+
+    /// Returns the candidates, best first
+    #[must_use]
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    /// Returns the index of the selected candidate, if one is
+    #[must_use]
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Returns the text the word should be replaced with: the selected
+    /// candidate, or the typed prefix
+    #[must_use]
+    pub fn text(&self) -> &str {
+        self.selected
+            .and_then(|index| self.candidates.get(index))
+            .unwrap_or(&self.prefix)
+    }
+
+    /// Selects the next candidate, going back to the typed prefix after the
+    /// last one
+    pub fn select_next(&mut self) {
+        self.selected = match self.selected {
+            None => Some(0),
+            Some(index) if index.saturating_add(1) < self.candidates.len() => Some(index.saturating_add(1)),
+            Some(_) => None,
+        };
+    }
+
+    /// Selects the previous candidate, going back to the typed prefix
+    /// before the first one
+    pub fn select_previous(&mut self) {
+        self.selected = match self.selected {
+            None => self.candidates.len().checked_sub(1),
+            Some(index) => index.checked_sub(1),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_cycle() {
+        let candidates = vec!["alpha".to_owned(), "alphabet".to_owned()];
+        let mut completion =
+            Completion::new(Position::default(), "al".to_owned(), candidates).expect("candidates");
+        assert_eq!(completion.text(), "al");
+        completion.select_next();
+        assert_eq!(completion.text(), "alpha");
+        completion.select_next();
+        completion.select_next();
+        assert_eq!(completion.text(), "al");
+        completion.select_previous();
+        assert_eq!(completion.text(), "alphabet");
+        assert!(Completion::new(Position::default(), "al".to_owned(), Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,155 @@
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+const CONFIG_FILE_NAME: &str = "config.ini";
+
+/// A `[name]` section of an INI-like file with its `key = value` entries
+/// in the order they were written.
+#[derive(Default)]
+pub struct Section {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+/// The parsed contents of an INI-like data file.
+///
+/// Entries written before the first `[section]` header are stored in a
+/// section with an empty name.
+#[derive(Default)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+impl Section {
+    /// Returns the name of the section
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the `key = value` pairs of the section
+    #[must_use]
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// Returns the value of the last entry with the given `key`
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|&&(ref entry_key, _)| entry_key == key)
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+impl Config {
+    /// Loads the user configuration from `config.ini` in the config
+    /// directory. A missing file results in an empty configuration.
+    ///
+    /// # Errors
+    /// It will return `Err` if the file exists but cannot be read or parsed
+    pub fn load() -> Result<Self, Error> {
+        if let Some(path) = config_dir().map(|dir| dir.join(CONFIG_FILE_NAME)) {
+            if path.is_file() {
+                let contents = fs::read_to_string(&path)?;
+                return Self::parse(&contents).map_err(|error| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{}: {}", path.display(), error),
+                    )
+                });
+            }
+        }
+        Ok(Self::default())
+    }
+
+    /// Parses the contents of an INI-like file.
+    ///
+    /// # Errors
+    /// It will return `Err` if a line is neither a comment, a section header
+    /// nor a `key = value` pair
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let mut sections = vec![Section::default()];
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push(Section {
+                    name: name.trim().to_owned(),
+                    entries: Vec::new(),
+                });
+            } else if let Some((key, value)) = line.split_once('=') {
+                if let Some(section) = sections.last_mut() {
+                    section
+                        .entries
+                        .push((key.trim().to_owned(), value.trim().to_owned()));
+                }
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: expected `key = value`", number.saturating_add(1)),
+                ));
+            }
+        }
+        Ok(Self { sections })
+    }
+
+    /// Returns every section with the given `name`
+    pub fn sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Section> {
+        self.sections
+            .iter()
+            .filter(move |section| section.name == name)
+    }
+
+    /// Returns the last section with the given `name`
+    #[must_use]
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().rev().find(|section| section.name == name)
+    }
+
+    /// Returns the value of `key` in the section `name`
+    #[must_use]
+    pub fn get(&self, name: &str, key: &str) -> Option<&str> {
+        self.sections
+            .iter()
+            .rev()
+            .filter(|section| section.name == name)
+            .find_map(|section| section.get(key))
+    }
+}
+
+/// Returns the directory holding the user's hammare configuration:
+/// `$XDG_CONFIG_HOME/hammare`, falling back to `$HOME/.config/hammare`.
+#[must_use]
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("hammare"));
+    }
+    env::var_os("HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join(".config").join("hammare"))
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "# comment\ntop = level\n[keys]\nCtrl-K Ctrl-C = help\n\n[keys]\nCtrl-Q = quit\n",
+        )
+        .expect("valid config");
+        assert_eq!(config.get("", "top"), Some("level"));
+        assert_eq!(config.get("keys", "Ctrl-K Ctrl-C"), Some("help"));
+        assert_eq!(config.get("keys", "Ctrl-Q"), Some("quit"));
+        assert_eq!(config.sections("keys").count(), 2);
+        assert!(Config::parse("[keys]\nnot a pair").is_err());
+    }
+}
@@ -0,0 +1,472 @@
+use std::cmp;
+
+/// Number of inserted and deleted lines beyond which two texts are not
+/// compared line by line anymore, and differ as a whole
+const MAX_EDIT_COST: usize = 1000;
+
+/// A run of consecutive lines that differ between two texts
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Hunk {
+    /// The index of the first line removed from the old text, or of the
+    /// line the new lines come before
+    pub old_start: usize,
+    pub old_len: usize,
+    /// The index of the first line added to the new text, or of the line
+    /// following the removed lines
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+/// How the lines of a hunk changed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Hunk {
+    /// Tells whether the hunk only adds or removes lines, or replaces some
+    #[must_use]
+    pub fn change(&self) -> Change {
+        if self.old_len == 0 {
+            Change::Added
+        } else if self.new_len == 0 {
+            Change::Deleted
+        } else {
+            Change::Modified
+        }
+    }
+}
+
+/// Returns the lines of a unified diff turning `old` into `new`, with
+/// `context` unchanged lines around each hunk. Each line comes with the
+/// index of the line of the new text it shows, or that follows the lines
+/// it removes.
+#[must_use]
+pub fn unified<S: AsRef<str> + PartialEq>(old: &[S], new: &[S], context: usize) -> Vec<(String, usize)> {
+    let hunks = diff(old, new);
+    let mut lines = Vec::new();
+    let mut group_start = 0;
+    while let Some(first) = hunks.get(group_start) {
+        // Hunks closer than twice the context share their context lines
+        let mut group_end = group_start.saturating_add(1);
+        while let Some(next) = hunks.get(group_end) {
+            let previous_end = hunks
+                .get(group_end.saturating_sub(1))
+                .map_or(0, |hunk| hunk.old_start.saturating_add(hunk.old_len));
+            if next.old_start.saturating_sub(previous_end) > context.saturating_mul(2) {
+                break;
+            }
+            group_end = group_end.saturating_add(1);
+        }
+        let group = hunks.get(group_start..group_end).unwrap_or_default();
+        let last = group.last().unwrap_or(first);
+        let old_from = first.old_start.saturating_sub(context);
+        let new_from = first.new_start.saturating_sub(first.old_start.saturating_sub(old_from));
+        let old_to = cmp::min(last.old_start.saturating_add(last.old_len).saturating_add(context), old.len());
+        let new_to = last
+            .new_start
+            .saturating_add(last.new_len)
+            .saturating_add(old_to.saturating_sub(last.old_start.saturating_add(last.old_len)));
+        lines.push((
+            format!(
+                "@@ -{} +{} @@",
+                range_header(old_from, old_to),
+                range_header(new_from, new_to)
+            ),
+            first.new_start,
+        ));
+        let (mut old_index, mut new_index) = (old_from, new_from);
+        for hunk in group {
+            while old_index < hunk.old_start {
+                let line = old.get(old_index).map_or("", AsRef::as_ref);
+                lines.push((format!(" {line}"), new_index));
+                old_index = old_index.saturating_add(1);
+                new_index = new_index.saturating_add(1);
+            }
+            for line in old.iter().skip(hunk.old_start).take(hunk.old_len) {
+                lines.push((format!("-{}", line.as_ref()), hunk.new_start));
+            }
+            for (offset, line) in new.iter().skip(hunk.new_start).take(hunk.new_len).enumerate() {
+                lines.push((format!("+{}", line.as_ref()), hunk.new_start.saturating_add(offset)));
+            }
+            old_index = hunk.old_start.saturating_add(hunk.old_len);
+            new_index = hunk.new_start.saturating_add(hunk.new_len);
+        }
+        while old_index < old_to {
+            let line = old.get(old_index).map_or("", AsRef::as_ref);
+            lines.push((format!(" {line}"), new_index));
+            old_index = old_index.saturating_add(1);
+            new_index = new_index.saturating_add(1);
+        }
+        group_start = group_end;
+    }
+    lines
+}
+
+/// Writes the lines from `start` up to `end` as in a hunk header: the first
+/// line counting from 1, or the line before when there is none, and the
+/// number of lines
+fn range_header(start: usize, end: usize) -> String {
+    let len = end.saturating_sub(start);
+    let first = if len == 0 { start } else { start.saturating_add(1) };
+    format!("{first},{len}")
+}
+
+/// The hunks turning a fixed old text into a text being edited. Each update
+/// only compares again the lines that changed since the previous one, along
+/// with the hunks they touch, and moves the hunks after them.
+pub struct Tracker {
+    old: Vec<String>,
+    /// The new text as of the last update
+    new: Vec<String>,
+    hunks: Vec<Hunk>,
+}
+
+impl Tracker {
+    /// Starts tracking the changes from `old`, which the new text is equal
+    /// to at first
+    #[must_use]
+    pub fn new(old: Vec<String>) -> Self {
+        Self {
+            new: old.clone(),
+            old,
+            hunks: Vec::new(),
+        }
+    }
+
+    /// Returns the hunks turning the old text into the new one, as of the
+    /// last update
+    #[must_use]
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// Finds the hunks turning the old text into `new`
+    pub fn update(&mut self, new: &[&str]) {
+        let prefix = self
+            .new
+            .iter()
+            .zip(new)
+            .take_while(|&(previous, line)| previous == line)
+            .count();
+        if prefix == self.new.len() && prefix == new.len() {
+            return;
+        }
+        let suffix = self
+            .new
+            .iter()
+            .skip(prefix)
+            .rev()
+            .zip(new.iter().skip(prefix).rev())
+            .take_while(|&(previous, line)| previous == line)
+            .count();
+        // The lines that changed, widened to the hunks they touch
+        let mut start = prefix;
+        let mut previous_end = self.new.len().saturating_sub(suffix);
+        let touching = |hunk: &Hunk, from: usize, to: usize| {
+            hunk.new_start <= to && hunk.new_start.saturating_add(hunk.new_len) >= from
+        };
+        let first = self
+            .hunks
+            .iter()
+            .position(|hunk| touching(hunk, start, previous_end) || hunk.new_start > previous_end)
+            .unwrap_or(self.hunks.len());
+        let last = self
+            .hunks
+            .iter()
+            .skip(first)
+            .take_while(|hunk| touching(hunk, start, previous_end))
+            .count()
+            .saturating_add(first);
+        let touched = self.hunks.get(first..last).unwrap_or_default();
+        if let (Some(first_touched), Some(last_touched)) = (touched.first(), touched.last()) {
+            start = cmp::min(start, first_touched.new_start);
+            previous_end = cmp::max(previous_end, last_touched.new_start.saturating_add(last_touched.new_len));
+        }
+        let new_end = new.len().saturating_sub(self.new.len().saturating_sub(previous_end));
+        // Outside of hunks, the lines of the old text are shifted by the
+        // lines the hunks before them added or removed
+        let (added_before, removed_before) = line_counts(self.hunks.get(..first).unwrap_or_default());
+        let (added, removed) = line_counts(self.hunks.get(..last).unwrap_or_default());
+        let old_start = start.saturating_add(removed_before).saturating_sub(added_before);
+        let old_end = previous_end.saturating_add(removed).saturating_sub(added);
+        let old = self.old.get(old_start..old_end).unwrap_or_default();
+        let changed = new.get(start..new_end).unwrap_or_default();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let hunks = diff(&old, changed).into_iter().map(|hunk| Hunk {
+            old_start: hunk.old_start.saturating_add(old_start),
+            new_start: hunk.new_start.saturating_add(start),
+            ..hunk
+        });
+        let after: Vec<Hunk> = self
+            .hunks
+            .drain(last..)
+            .map(|hunk| Hunk {
+                new_start: hunk.new_start.saturating_sub(previous_end).saturating_add(new_end),
+                ..hunk
+            })
+            .collect();
+        self.hunks.truncate(first);
+        self.hunks.extend(hunks);
+        self.hunks.extend(after);
+        self.new.splice(start..previous_end, changed.iter().map(|&line| line.to_owned()));
+    }
+}
+
+/// Returns the numbers of lines added and removed by `hunks`
+fn line_counts(hunks: &[Hunk]) -> (usize, usize) {
+    hunks.iter().fold((0, 0), |(added, removed): (usize, usize), hunk| {
+        (added.saturating_add(hunk.new_len), removed.saturating_add(hunk.old_len))
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// Returns the hunks turning `old` into `new`, in order. The lines both
+/// texts start and end with are skipped before the rest is compared with
+/// the Myers algorithm, which finds the fewest insertions and deletions.
+#[must_use]
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let old_rest = old.get(prefix..).unwrap_or_default();
+    let new_rest = new.get(prefix..).unwrap_or_default();
+    let suffix = old_rest
+        .iter()
+        .rev()
+        .zip(new_rest.iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    let old_middle = old_rest.get(..old_rest.len().saturating_sub(suffix)).unwrap_or_default();
+    let new_middle = new_rest.get(..new_rest.len().saturating_sub(suffix)).unwrap_or_default();
+    if old_middle.is_empty() && new_middle.is_empty() {
+        return Vec::new();
+    }
+    let Some(edits) = edit_script(old_middle, new_middle) else {
+        return vec![Hunk {
+            old_start: prefix,
+            old_len: old_middle.len(),
+            new_start: prefix,
+            new_len: new_middle.len(),
+        }];
+    };
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut old_index, mut new_index) = (prefix, prefix);
+    let mut in_hunk = false;
+    for edit in edits {
+        if edit != Edit::Keep && !in_hunk {
+            hunks.push(Hunk {
+                old_start: old_index,
+                old_len: 0,
+                new_start: new_index,
+                new_len: 0,
+            });
+        }
+        in_hunk = edit != Edit::Keep;
+        let hunk = hunks.last_mut().filter(|_| in_hunk);
+        match edit {
+            Edit::Keep => {
+                old_index = old_index.saturating_add(1);
+                new_index = new_index.saturating_add(1);
+            }
+            Edit::Delete => {
+                old_index = old_index.saturating_add(1);
+                if let Some(hunk) = hunk {
+                    hunk.old_len = hunk.old_len.saturating_add(1);
+                }
+            }
+            Edit::Insert => {
+                new_index = new_index.saturating_add(1);
+                if let Some(hunk) = hunk {
+                    hunk.new_len = hunk.new_len.saturating_add(1);
+                }
+            }
+        }
+    }
+    hunks
+}
+
+/// The furthest points reached on each diagonal after some number of
+/// edits. Diagonal `k` holds the points where `x + m - y == k`, `x` and `y`
+/// being the lines reached in the old and the new text, and `m` the length
+/// of the new one. Only every other diagonal from `start` can be reached,
+/// so only those are kept.
+struct Frontier {
+    start: usize,
+    x: Vec<Option<usize>>,
+}
+
+impl Frontier {
+    fn get(&self, k: usize) -> Option<usize> {
+        let offset = k.checked_sub(self.start)?;
+        if offset % 2 == 1 {
+            return None;
+        }
+        self.x.get(offset / 2).copied().flatten()
+    }
+}
+
+/// Returns where the best path reaching diagonal `k` gets with one more
+/// edit from `frontier`, before following the equal lines, and whether
+/// that edit is an insertion
+fn next_move(frontier: &Frontier, k: usize, n: usize) -> Option<(usize, bool)> {
+    let insertion = frontier.get(k.saturating_add(1)).filter(|&x| x <= k);
+    let deletion = k
+        .checked_sub(1)
+        .and_then(|k| frontier.get(k))
+        .map(|x| x.saturating_add(1))
+        .filter(|&x| x <= n);
+    match (insertion, deletion) {
+        (Some(inserted), Some(deleted)) if deleted > inserted => Some((deleted, false)),
+        (Some(inserted), _) => Some((inserted, true)),
+        (None, Some(deleted)) => Some((deleted, false)),
+        (None, None) => None,
+    }
+}
+
+/// Returns the shortest list of edits turning `old` into `new`, or `None`
+/// when it would take more than `MAX_EDIT_COST` insertions and deletions
+fn edit_script<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<Edit>> {
+    let (n, m) = (old.len(), new.len());
+    let equal = |x: usize, y: usize| old.get(x).is_some() && old.get(x) == new.get(y);
+    let mut x = 0;
+    while equal(x, x) {
+        x = x.saturating_add(1);
+    }
+    if x == n && x == m {
+        return Some(vec![Edit::Keep; n]);
+    }
+    let mut trace = vec![Frontier {
+        start: m,
+        x: vec![Some(x)],
+    }];
+    for cost in 1..=MAX_EDIT_COST {
+        let start = m.checked_sub(cost).unwrap_or(m.saturating_add(cost) % 2);
+        let end = cmp::min(m.saturating_add(cost), n.saturating_add(m));
+        let previous = trace.last()?;
+        let mut frontier = Frontier {
+            start,
+            x: vec![None; (end.saturating_sub(start) / 2).saturating_add(1)],
+        };
+        for k in (start..=end).step_by(2) {
+            let Some((mut x, _)) = next_move(previous, k, n) else {
+                continue;
+            };
+            let mut y = x.saturating_add(m).saturating_sub(k);
+            while equal(x, y) {
+                x = x.saturating_add(1);
+                y = y.saturating_add(1);
+            }
+            if x == n && y == m {
+                return backtrack(&trace, n, m);
+            }
+            if let Some(slot) = frontier.x.get_mut(k.saturating_sub(start) / 2) {
+                *slot = Some(x);
+            }
+        }
+        trace.push(frontier);
+    }
+    None
+}
+
+/// Follows the path ending at the end of both texts back through the
+/// frontiers that led to it
+fn backtrack(trace: &[Frontier], n: usize, m: usize) -> Option<Vec<Edit>> {
+    let (mut x, mut y) = (n, m);
+    let mut edits = Vec::new();
+    for frontier in trace.iter().rev() {
+        let k = x.saturating_add(m).saturating_sub(y);
+        let (moved_x, insertion) = next_move(frontier, k, n)?;
+        let moved_y = moved_x.saturating_add(m).saturating_sub(k);
+        while x > moved_x {
+            edits.push(Edit::Keep);
+            x = x.saturating_sub(1);
+        }
+        if insertion {
+            edits.push(Edit::Insert);
+            y = moved_y.saturating_sub(1);
+        } else {
+            edits.push(Edit::Delete);
+            x = moved_x.saturating_sub(1);
+            y = moved_y;
+        }
+    }
+    edits.extend((0..x).map(|_| Edit::Keep));
+    edits.reverse();
+    Some(edits)
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    fn hunk(old_start: usize, old_len: usize, new_start: usize, new_len: usize) -> Hunk {
+        Hunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(&["a", "b"], &["a", "b"]), []);
+        assert_eq!(diff(&["a", "b", "c"], &["a", "x", "c"]), [hunk(1, 1, 1, 1)]);
+        assert_eq!(diff(&["a"], &["a", "b", "c"]), [hunk(1, 0, 1, 2)]);
+        assert_eq!(diff(&["a", "b", "c"], &["c"]), [hunk(0, 2, 0, 0)]);
+        assert_eq!(
+            diff(&["a", "b", "c", "d", "e", "f"], &["b", "c", "x", "e", "f", "g"]),
+            [hunk(0, 1, 0, 0), hunk(3, 1, 2, 1), hunk(6, 0, 5, 1)]
+        );
+        assert_eq!(diff::<&str>(&[], &["a"]), [hunk(0, 0, 0, 1)]);
+        assert_eq!(hunk(3, 1, 2, 1).change(), Change::Modified);
+        assert_eq!(hunk(0, 1, 0, 0).change(), Change::Deleted);
+
+        let old: Vec<usize> = (0..3000).collect();
+        let new: Vec<usize> = (0..3000).map(|line| line * 2 + 1).collect();
+        assert_eq!(diff(&old, &new), [hunk(0, 3000, 0, 3000)]);
+    }
+
+    #[test]
+    fn test_tracker() {
+        let old = ["a", "b", "c", "d", "e", "f"];
+        let mut tracker = Tracker::new(old.iter().map(|&line| line.to_owned()).collect());
+        tracker.update(&old);
+        assert_eq!(tracker.hunks(), []);
+        tracker.update(&["a", "x", "c", "d", "e", "f"]);
+        assert_eq!(tracker.hunks(), [hunk(1, 1, 1, 1)]);
+        // A later edit moves the hunks after it
+        tracker.update(&["new", "a", "x", "c", "d", "e", "f"]);
+        assert_eq!(tracker.hunks(), [hunk(0, 0, 0, 1), hunk(1, 1, 2, 1)]);
+        tracker.update(&["new", "a", "x", "c", "d", "f", "g"]);
+        assert_eq!(tracker.hunks(), [hunk(0, 0, 0, 1), hunk(1, 1, 2, 1), hunk(4, 1, 5, 0), hunk(6, 0, 6, 1)]);
+        // Hunks an edit touches are compared again
+        tracker.update(&["a", "b", "c", "d", "f", "g"]);
+        assert_eq!(tracker.hunks(), [hunk(4, 1, 4, 0), hunk(6, 0, 5, 1)]);
+        tracker.update(&old);
+        assert_eq!(tracker.hunks(), []);
+    }
+
+    #[test]
+    fn test_unified() {
+        let old = ["a", "b", "c", "d", "e", "f", "g", "h", "i"];
+        let new = ["a", "B", "c", "d", "e", "f", "g", "h"];
+        let lines: Vec<(String, usize)> = unified(&old[..], &new[..], 1);
+        let text: Vec<&str> = lines.iter().map(|&(ref line, _)| line.as_str()).collect();
+        assert_eq!(
+            text,
+            ["@@ -1,3 +1,3 @@", " a", "-b", "+B", " c", "@@ -8,2 +8,1 @@", " h", "-i"]
+        );
+        let rows: Vec<usize> = lines.iter().map(|&(_, row)| row).collect();
+        assert_eq!(rows, [1, 0, 1, 1, 2, 8, 7, 8]);
+        assert_eq!(unified(&["a"], &["a", "b"], 3).first().map(|line| line.0.as_str()), Some("@@ -1,1 +1,2 @@"));
+        assert_eq!(unified(&[""; 0], &["a"], 3).first().map(|line| line.0.as_str()), Some("@@ -0,0 +1,1 @@"));
+    }
+}
@@ -1,9 +1,46 @@
+use crate::diff::{self, Hunk};
+use crate::highlighting::State;
+use crate::lsp;
+use crate::row::BRACKETS;
 use crate::FileType;
+use crate::HighlightingOptions;
 use crate::Position;
 use crate::Row;
 use crate::SearchDirection;
+use std::cmp;
+use std::collections::HashSet;
 use std::fs;
 use std::io::{Error, Write};
+use std::os::unix::fs::MetadataExt;
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Number of rows at the start and at the end of a file where a shebang or
+/// a modeline is looked for
+const DETECTION_ROWS: usize = 5;
+
+/// Number of rows searched for a matching bracket in each direction
+const MAX_BRACKET_ROWS: usize = 1000;
+
+/// What tells a version of a file on disk from another
+#[derive(PartialEq, Eq, Clone, Debug)]
+struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+}
+
+impl DiskState {
+    /// Reads the state of the file called `file_name`, if it exists
+    fn of(file_name: &str) -> Option<Self> {
+        let metadata = fs::metadata(file_name).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode: metadata.ino(),
+        })
+    }
+}
 
 #[derive(Default)]
 pub struct Document {
@@ -11,6 +48,28 @@ pub struct Document {
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
+    file_type_overridden: bool,
+    /// The file name the file type was detected from, so that saving only
+    /// detects it again under a new name
+    detected_for: Option<String>,
+    /// Rows before this index have an up to date highlighting, each one
+    /// starting in the lexer state the previous one ended in
+    highlighted_until: usize,
+    /// Counts the edits, so that a language server can tell the versions of
+    /// the text apart
+    version: usize,
+    /// The name of a scratch document, which has no file and is read-only
+    title: Option<String>,
+    /// For each row of a scratch document, the file and the position it
+    /// refers to, if it does
+    links: Vec<Option<(String, Position)>>,
+    /// The changes from the lines of the file in the last commit, when it
+    /// is in a git repository
+    changes: Option<diff::Tracker>,
+    /// The version of the text the changes were found in
+    changes_version: Option<usize>,
+    /// The state of the file when it was last read or written
+    disk_state: Option<DiskState>,
 }
 
 impl Document {
@@ -20,17 +79,152 @@ impl Document {
     /// It will return `Err` if it fails to open the file
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
         let contents = fs::read_to_string(filename)?;
-        let file_type = FileType::from(filename);
         let mut rows = Vec::new();
         for value in contents.lines() {
             rows.push(Row::from(value));
         }
-        Ok(Self {
+        let mut document = Self {
             rows,
             file_name: Some(filename.to_owned()),
             dirty: false,
-            file_type,
-        })
+            file_type: FileType::default(),
+            file_type_overridden: false,
+            detected_for: None,
+            highlighted_until: 0,
+            version: 0,
+            title: None,
+            links: Vec::new(),
+            changes: None,
+            changes_version: None,
+            disk_state: DiskState::of(filename),
+        };
+        document.detect_file_type();
+        Ok(document)
+    }
+
+    /// Creates an empty read-only document called `title`, to show the
+    /// output of the editor or of a command
+    #[must_use]
+    pub fn scratch(title: &str) -> Self {
+        Self {
+            title: Some(title.to_owned()),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the name of a scratch document
+    #[must_use]
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Checks if the document is a read-only scratch document
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.title.is_some()
+    }
+
+    /// Appends a row to a scratch document, optionally referring to a
+    /// position in a file. It does not count as an unsaved change.
+    pub fn push_line(&mut self, text: &str, link: Option<(String, Position)>) {
+        self.rows.push(Row::from(text));
+        self.links.resize(self.rows.len().saturating_sub(1), None);
+        self.links.push(link);
+        self.version = self.version.saturating_add(1);
+    }
+
+    /// Returns the file and the position the row at `index` refers to
+    #[must_use]
+    pub fn link(&self, index: usize) -> Option<&(String, Position)> {
+        self.links.get(index)?.as_ref()
+    }
+
+    /// Checks if the file was changed on disk since it was last read or
+    /// written by the editor
+    #[must_use]
+    pub fn changed_on_disk(&self) -> bool {
+        match (self.disk_state.as_ref(), self.file_name.as_ref()) {
+            (Some(state), Some(file_name)) => DiskState::of(file_name).map_or(false, |current| current != *state),
+            _ => false,
+        }
+    }
+
+    /// Takes the current version of the file on disk as the one the text is
+    /// based on, so that its changes are not reported anymore
+    pub fn record_disk_state(&mut self) {
+        self.disk_state = self.file_name.as_deref().and_then(DiskState::of);
+    }
+
+    /// Records that the text is the one of the file on disk, after it was
+    /// read again
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+        self.record_disk_state();
+    }
+
+    /// Sets the committed text the changes are shown against, or `None`
+    /// when the file is not committed
+    pub fn set_base(&mut self, base: Option<String>) {
+        self.changes = base.map(|text| diff::Tracker::new(text.lines().map(str::to_owned).collect()));
+        self.changes_version = None;
+    }
+
+    /// Checks if the document has a committed text to compare with
+    #[must_use]
+    pub fn has_base(&self) -> bool {
+        self.changes.is_some()
+    }
+
+    /// Compares the text with its committed version again, if it changed
+    /// since the last time. Returns whether it did.
+    pub fn update_changes(&mut self) -> bool {
+        let Some(ref mut changes) = self.changes else {
+            return false;
+        };
+        if self.changes_version == Some(self.version) {
+            return false;
+        }
+        let rows: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        changes.update(&rows);
+        self.changes_version = Some(self.version);
+        true
+    }
+
+    /// Returns the hunks changed since the last commit, as of the last call
+    /// to `update_changes`
+    #[must_use]
+    pub fn changes(&self) -> &[Hunk] {
+        self.changes.as_ref().map_or(&[], diff::Tracker::hunks)
+    }
+
+    /// Detects the file type from the file name and the first and last rows
+    fn detect_file_type(&mut self) {
+        let file_name = self.file_name.clone().unwrap_or_default();
+        let tail_start = cmp::max(DETECTION_ROWS, self.rows.len().saturating_sub(DETECTION_ROWS));
+        let lines: Vec<&str> = self
+            .rows
+            .iter()
+            .take(DETECTION_ROWS)
+            .chain(self.rows.iter().skip(tail_start))
+            .map(Row::as_str)
+            .collect();
+        self.file_type = FileType::detect(&file_name, &lines);
+        self.detected_for = self.file_name.clone();
+    }
+
+    /// Overrides the detected file type until the document is closed
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.file_type = file_type;
+        self.file_type_overridden = true;
+        self.rehighlight_all();
+    }
+
+    /// Drops the highlighting of every row, after the file type changed
+    fn rehighlight_all(&mut self) {
+        for row in &mut self.rows {
+            row.invalidate_highlighting();
+        }
+        self.highlighted_until = 0;
     }
 
     /// Gets the name of the file that we are opening on the editor
@@ -39,6 +233,71 @@ impl Document {
         self.file_type.name()
     }
 
+    /// Returns the highlighting options of the file type
+    #[must_use]
+    pub fn highlighting_options(&self) -> &HighlightingOptions {
+        self.file_type.highlighting_options()
+    }
+
+    /// Returns the command starting the language server of the file type,
+    /// if one is configured
+    #[must_use]
+    pub fn language_server(&self) -> Option<&str> {
+        self.file_type.language_server()
+    }
+
+    /// Returns the command formatting the document, if its file type has
+    /// one
+    #[must_use]
+    pub fn formatter(&self) -> Option<&str> {
+        self.file_type.formatter()
+    }
+
+    /// Returns the identifier of the file type for language servers
+    #[must_use]
+    pub fn language_id(&self) -> String {
+        self.file_type.language_id()
+    }
+
+    /// Returns the number of edits made since the document was opened
+    #[must_use]
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Returns the whole text, each row ending with a newline as when saved
+    #[must_use]
+    pub fn text(&self) -> String {
+        self.rows.iter().map(|row| format!("{}\n", row.as_str())).collect()
+    }
+
+    /// Converts `at` to a language server position
+    #[must_use]
+    pub fn lsp_position(&self, at: &Position) -> lsp::Position {
+        lsp::Position {
+            line: at.y,
+            character: self.rows.get(at.y).map_or(0, |row| row.utf16_offset(at.x)),
+        }
+    }
+
+    /// Converts a language server position to a position in the document
+    #[must_use]
+    pub fn position_from_lsp(&self, position: lsp::Position) -> Position {
+        Position {
+            x: self
+                .rows
+                .get(position.line)
+                .map_or(0, |row| row.index_at_utf16(position.character)),
+            y: position.line,
+        }
+    }
+
+    /// Returns the character at `at`, if there is one
+    #[must_use]
+    pub fn char_at(&self, at: &Position) -> Option<char> {
+        self.rows.get(at.y).and_then(|row| row.char_at(at.x))
+    }
+
     /// Gets the row based on an `index`
     #[must_use]
     pub fn row(&self, index: usize) -> Option<&Row> {
@@ -87,7 +346,7 @@ impl Document {
         if at.y > self.rows.len() {
             return;
         }
-        self.dirty = true;
+        self.changed();
         if c == '\n' {
             self.insert_newline(at);
         } else if at.y == self.rows.len() {
@@ -102,11 +361,54 @@ impl Document {
         self.unhighlight_rows(at.y);
     }
 
-    fn unhighlight_rows(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start) {
-            row.is_highlighted = false;
+    /// Inserts `text`, which may span several lines, at `at`. Returns the
+    /// position at the end of the inserted text.
+    pub fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.rows.len() || text.is_empty() {
+            return at.clone();
+        }
+        if at.y == self.rows.len() {
+            self.rows.push(Row::default());
+        }
+        let mut lines = text.split('\n');
+        let first = Row::from(lines.next().unwrap_or_default());
+        let mut end = Position {
+            x: at.x.saturating_add(first.len()),
+            y: at.y,
+        };
+        let Some(row) = self.rows.get_mut(at.y) else {
+            return at.clone();
+        };
+        let tail = row.split(at.x);
+        row.append(&first);
+        for line in lines {
+            let row = Row::from(line);
+            end = Position {
+                x: row.len(),
+                y: end.y.saturating_add(1),
+            };
+            self.rows.insert(end.y, row);
         }
+        if let Some(row) = self.rows.get_mut(end.y) {
+            row.append(&tail);
+        }
+        self.changed();
+        self.unhighlight_rows(at.y);
+        end
+    }
+
+    /// Records an edit of the text
+    fn changed(&mut self) {
+        self.dirty = true;
+        self.version = self.version.saturating_add(1);
+    }
+
+    /// Marks the rows from `start` on as possibly needing a new
+    /// highlighting. The rows that changed already dropped theirs; the
+    /// following ones are only highlighted again if the lexer state they
+    /// start in changed.
+    fn unhighlight_rows(&mut self, start: usize) {
+        self.highlighted_until = cmp::min(self.highlighted_until, start);
     }
 
     /// Deletes a single or multiple characters in the document
@@ -116,7 +418,7 @@ impl Document {
         if at.y >= len {
             return;
         }
-        self.dirty = true;
+        self.changed();
         if at.x == self.rows.get_mut(at.y).expect("Something unexpected happened while trying to get a mutable reference to the row index").len() && at.y + 1 < len {
             let next_row = self.rows.remove(at.y + 1);
             let row = self.rows.get_mut(at.y).expect("Something unexpected happened while trying to get a mutable reference to the row index");
@@ -128,6 +430,310 @@ impl Document {
         self.unhighlight_rows(at.y);
     }
 
+    /// Comments out the rows from `start` to `end` included with the first
+    /// line comment token of the file type, placed at the smallest
+    /// indentation of the rows, or uncomments them if they all are
+    /// commented already. Blank rows are left alone.
+    ///
+    /// Returns whether the rows were commented, or `None` if the file type
+    /// has no line comments.
+    pub fn toggle_comment(&mut self, start: usize, end: usize) -> Option<bool> {
+        let token = self.file_type.highlighting_options().line_comments().first()?.clone();
+        let end = cmp::min(end.saturating_add(1), self.rows.len());
+        let rows = || self.rows.iter().take(end).skip(start).filter(|row| !row.is_blank());
+        let uncomment = rows().all(|row| row.contains_at(row.indentation(), &token));
+        let indentation = rows().map(Row::indentation).min().unwrap_or(0);
+        let token_len = token.graphemes(true).count();
+        for row in self.rows.iter_mut().take(end).skip(start).filter(|row| !row.is_blank()) {
+            if uncomment {
+                let at = row.indentation();
+                let mut comment_end = at.saturating_add(token_len);
+                if row.contains_at(comment_end, " ") {
+                    comment_end = comment_end.saturating_add(1);
+                }
+                row.delete_range(at, comment_end);
+            } else {
+                row.insert_str(indentation, &format!("{token} "));
+            }
+        }
+        self.changed();
+        self.unhighlight_rows(start);
+        Some(!uncomment)
+    }
+
+    /// Indents the rows from `start` to `end` included with `unit`, leaving
+    /// blank rows alone. Returns the number of graphemes added to each row.
+    pub fn indent(&mut self, start: usize, end: usize, unit: &str) -> Vec<usize> {
+        let unit_len = unit.graphemes(true).count();
+        let end = cmp::min(end.saturating_add(1), self.rows.len());
+        let mut added = Vec::new();
+        for row in self.rows.iter_mut().take(end).skip(start) {
+            if row.is_blank() {
+                added.push(0);
+            } else {
+                row.insert_str(0, unit);
+                added.push(unit_len);
+            }
+        }
+        self.changed();
+        self.unhighlight_rows(start);
+        added
+    }
+
+    /// Removes one level of indentation, a tab or up to `width` spaces, from
+    /// the rows from `start` to `end` included. Returns the number of
+    /// graphemes removed from each row.
+    pub fn outdent(&mut self, start: usize, end: usize, width: usize) -> Vec<usize> {
+        let end = cmp::min(end.saturating_add(1), self.rows.len());
+        let mut removed = Vec::new();
+        for row in self.rows.iter_mut().take(end).skip(start) {
+            let count = if row.contains_at(0, "\t") {
+                1
+            } else {
+                (0..width).take_while(|&index| row.contains_at(index, " ")).count()
+            };
+            row.delete_range(0, count);
+            removed.push(count);
+        }
+        if removed.iter().any(|&count| count > 0) {
+            self.changed();
+            self.unhighlight_rows(start);
+        }
+        removed
+    }
+
+    /// Returns the contents of the rows from `start` to `end` included
+    #[must_use]
+    pub fn lines(&self, start: usize, end: usize) -> Vec<String> {
+        self.rows
+            .iter()
+            .take(end.saturating_add(1))
+            .skip(start)
+            .map(|row| row.as_str().to_owned())
+            .collect()
+    }
+
+    /// Replaces the whole text, keeping the rows it starts and ends with.
+    /// Returns the first row that changed, followed by the end of the
+    /// changed rows before and after, or `None` when the text is the same.
+    pub fn set_text(&mut self, text: &str) -> Option<(usize, usize, usize)> {
+        let lines: Vec<&str> = text.lines().collect();
+        let start = self
+            .rows
+            .iter()
+            .zip(&lines)
+            .take_while(|&(row, line)| row.as_str() == *line)
+            .count();
+        if start == self.rows.len() && start == lines.len() {
+            return None;
+        }
+        let common_end = self
+            .rows
+            .iter()
+            .skip(start)
+            .rev()
+            .zip(lines.iter().skip(start).rev())
+            .take_while(|&(row, line)| row.as_str() == *line)
+            .count();
+        let old_end = self.rows.len().saturating_sub(common_end);
+        let new_end = lines.len().saturating_sub(common_end);
+        self.rows.splice(
+            start..old_end,
+            lines.get(start..new_end).unwrap_or_default().iter().map(|&line| Row::from(line)),
+        );
+        self.changed();
+        self.unhighlight_rows(start);
+        Some((start, old_end, new_end))
+    }
+
+    /// Returns the text from `start` up to `end`, with a newline between
+    /// the rows
+    #[must_use]
+    pub fn text_between(&self, start: &Position, end: &Position) -> String {
+        self.rows
+            .iter()
+            .enumerate()
+            .take(end.y.saturating_add(1))
+            .skip(start.y)
+            .map(|(y, row)| {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { row.len() };
+                row.substring(from, to)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Replaces the text from `start` up to `end` with `text`, which may
+    /// span several lines. Returns the position at the end of the inserted
+    /// text.
+    pub fn replace_range(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        let last = cmp::min(end.y, self.rows.len().saturating_sub(1));
+        if start.y <= last {
+            let tail = self.rows.get_mut(last).map(|row| row.split(end.x)).unwrap_or_default();
+            self.rows.drain(start.y.saturating_add(1)..=last);
+            if let Some(row) = self.rows.get_mut(start.y) {
+                row.delete_range(start.x, row.len());
+                row.append(&tail);
+            }
+            self.changed();
+            self.unhighlight_rows(start.y);
+        }
+        self.insert_text(start, text)
+    }
+
+    /// Replaces the rows from `start` to `end` included with `lines`
+    pub fn replace_rows(&mut self, start: usize, end: usize, lines: &[String]) {
+        let start = cmp::min(start, self.rows.len());
+        let end = cmp::max(start, cmp::min(end.saturating_add(1), self.rows.len()));
+        self.rows
+            .splice(start..end, lines.iter().map(|line| Row::from(line.as_str())));
+        self.changed();
+        self.unhighlight_rows(start);
+    }
+
+    /// Replaces the text from `start` up to `end` with the result of
+    /// `convert`, applied to the part of each row in between. Returns the
+    /// new position of `end`. Rows that convert to themselves are left
+    /// alone, and the document is not marked as changed if all of them do.
+    pub fn convert<F>(&mut self, start: &Position, end: &Position, convert: F) -> Position
+    where
+        F: Fn(&str) -> String,
+    {
+        let mut end_x = end.x;
+        let mut modified = false;
+        for (y, row) in self.rows.iter_mut().enumerate().take(end.y.saturating_add(1)).skip(start.y) {
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            let original = row.substring(from, to);
+            let converted = convert(&original);
+            if converted == original {
+                continue;
+            }
+            modified = true;
+            row.delete_range(from, to);
+            row.insert_str(from, &converted);
+            if y == end.y {
+                end_x = from.saturating_add(converted.graphemes(true).count());
+            }
+        }
+        if modified {
+            self.changed();
+            self.unhighlight_rows(start.y);
+        }
+        Position { x: end_x, y: end.y }
+    }
+
+    /// Returns the start and the text of the word ending at `at`, if any
+    #[must_use]
+    pub fn word_before(&self, at: &Position) -> Option<(Position, String)> {
+        self.rows
+            .get(at.y)?
+            .words()
+            .into_iter()
+            .find(|&(x, ref word)| x < at.x && x.saturating_add(word.graphemes(true).count()) == at.x)
+            .map(|(x, word)| (Position { x, y: at.y }, word))
+    }
+
+    /// Returns the distinct words of the document that start with `prefix`
+    /// and are longer, the closest ones to `at` first: on nearer rows, then
+    /// nearer in the row. The word at `at` itself is left out.
+    #[must_use]
+    pub fn completions(&self, at: &Position, prefix: &str) -> Vec<String> {
+        let mut found: Vec<((usize, usize), String)> = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, word) in row.words() {
+                if word.len() > prefix.len() && word.starts_with(prefix) && !(y == at.y && x == at.x) {
+                    found.push(((y.abs_diff(at.y), x.abs_diff(at.x)), word));
+                }
+            }
+        }
+        found.sort_by_key(|&(distance, _)| distance);
+        let mut seen: HashSet<String> = HashSet::with_capacity(found.len());
+        found
+            .into_iter()
+            .map(|(_, word)| word)
+            .filter(|word| seen.insert(word.clone()))
+            .collect()
+    }
+
+    /// Inserts a copy of the rows from `start` to `end` included right
+    /// after them. Returns the number of rows copied.
+    pub fn duplicate_rows(&mut self, start: usize, end: usize) -> usize {
+        let end = cmp::min(end.saturating_add(1), self.rows.len());
+        let copies: Vec<Row> = self
+            .rows
+            .iter()
+            .take(end)
+            .skip(start)
+            .map(|row| Row::from(row.as_str()))
+            .collect();
+        let count = copies.len();
+        self.rows.splice(end..end, copies);
+        self.changed();
+        self.unhighlight_rows(end);
+        count
+    }
+
+    /// Removes the rows from `start` to `end` included
+    pub fn delete_rows(&mut self, start: usize, end: usize) {
+        let end = cmp::min(end.saturating_add(1), self.rows.len());
+        if start >= end {
+            return;
+        }
+        self.rows.drain(start..end);
+        self.changed();
+        self.unhighlight_rows(start);
+    }
+
+    /// Moves the rows from `start` to `end` included one row up or down,
+    /// past their neighbor. Returns `false` if they are at the edge of the
+    /// document already.
+    pub fn move_rows(&mut self, start: usize, end: usize, direction: SearchDirection) -> bool {
+        let moved = if direction == SearchDirection::Forward {
+            self.rows
+                .get_mut(start..=end.saturating_add(1))
+                .map(|rows| rows.rotate_right(1))
+        } else {
+            start
+                .checked_sub(1)
+                .and_then(|above| self.rows.get_mut(above..=end))
+                .map(|rows| rows.rotate_left(1))
+        };
+        if moved.is_none() {
+            return false;
+        }
+        self.changed();
+        self.unhighlight_rows(start.saturating_sub(1));
+        true
+    }
+
+    /// Joins the rows from `start` to `end` included, or the row `start`
+    /// and the next one if they are the same, separating them with a single
+    /// space in place of the indentation of the joined rows. Returns the
+    /// position of the last join in the row, or `None` if there is nothing
+    /// to join.
+    pub fn join_rows(&mut self, start: usize, end: usize) -> Option<usize> {
+        let end = cmp::min(cmp::max(end, start.saturating_add(1)), self.rows.len().saturating_sub(1));
+        if start >= end {
+            return None;
+        }
+        let joined: Vec<Row> = self.rows.drain(start.saturating_add(1)..=end).collect();
+        let row = self.rows.get_mut(start)?;
+        let mut join_point = row.len();
+        for mut next in joined {
+            next.delete_range(0, next.indentation());
+            join_point = row.len();
+            if !row.is_empty() && !next.is_empty() && !row.as_str().ends_with(char::is_whitespace) {
+                row.append(&Row::from(" "));
+            }
+            row.append(&next);
+        }
+        self.changed();
+        self.unhighlight_rows(start);
+        Some(join_point)
+    }
+
     /// Saves the changes in the document
     ///
     /// # Errors
@@ -137,35 +743,144 @@ impl Document {
     pub fn save(&mut self) -> Result<(), Error> {
         if let Some(ref file_name) = self.file_name {
             let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
             for row in &mut self.rows {
                 file.write_all(row.as_bytes())?;
                 file.write_all(b"\n")?;
             }
             self.dirty = false;
+            self.disk_state = DiskState::of(file_name);
+            if !self.file_type_overridden && self.detected_for != self.file_name {
+                let previous = self.file_type.name();
+                self.detect_file_type();
+                if self.file_type.name() != previous {
+                    self.rehighlight_all();
+                }
+            }
         }
         Ok(())
     }
 
-    /// Loop over the rows and highligh the words that correspond
-    /// the word that was passed as a parameter.
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
-        let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
+    /// Highlights the rows from `start` up to `end`, along with the matches
+    /// of `word`. The lexer state at `start` comes from the rows above,
+    /// which are only lexed again from the first edited one, and only until
+    /// their end states match the cached ones again.
+    pub fn highlight(&mut self, word: &Option<String>, start: usize, end: usize) {
+        let end = cmp::min(end, self.rows.len());
+        let start = cmp::min(start, end);
+        self.highlight_until(start);
+        let mut state = self.start_state(start);
+        let opts = self.file_type.highlighting_options();
+        for row in self.rows.iter_mut().take(end).skip(start) {
+            state = row.highlight(opts, word, state);
+        }
+        self.highlighted_until = cmp::max(self.highlighted_until, end);
+    }
+
+    /// Brings the highlighting of the rows before `end` up to date, keeping
+    /// the search matches they were last highlighted with
+    fn highlight_until(&mut self, end: usize) {
+        let end = cmp::min(end, self.rows.len());
+        let start = self.highlighted_until;
+        if start >= end {
+            return;
+        }
+        let mut state = self.start_state(start);
+        let opts = self.file_type.highlighting_options();
+        for row in self.rows.iter_mut().take(end).skip(start) {
+            let word = row.highlighted_word().clone();
+            state = row.highlight(opts, &word, state);
+        }
+        self.highlighted_until = end;
+    }
+
+    /// Returns the lexer state the row at `index` starts in, as cached at
+    /// the end of the previous row
+    fn start_state(&self, index: usize) -> State {
+        index
+            .checked_sub(1)
+            .and_then(|previous| self.rows.get(previous))
+            .and_then(Row::end_state)
+            .unwrap_or_default()
+    }
+
+    /// Returns the bracket under the cursor, or right before it, and its
+    /// matching bracket. Otherwise returns the innermost pair of brackets
+    /// around the cursor. Brackets in strings and comments are ignored.
+    pub fn bracket_pair(&mut self, at: &Position) -> Option<(Position, Position)> {
+        self.highlight_until(at.y.saturating_add(MAX_BRACKET_ROWS));
+        let row = self.rows.get(at.y)?;
+        let brackets = row.brackets();
+        for x in [Some(at.x), at.x.checked_sub(1)].into_iter().flatten() {
+            if let Some(&(_, c)) = brackets.iter().find(|&&(index, _)| index == x) {
+                let position = Position { x, y: at.y };
+                return self
+                    .matching_bracket(&position, c)
+                    .map(|matching| (position, matching));
+            }
+        }
+        let mut depth: usize = 0;
+        for (position, c) in self.brackets_from(at, SearchDirection::Backward) {
+            if BRACKETS.iter().any(|&(_, close)| c == close) {
+                depth = depth.saturating_add(1);
+            } else if depth > 0 {
+                depth = depth.saturating_sub(1);
+            } else {
+                return self
+                    .matching_bracket(&position, c)
+                    .map(|matching| (position, matching));
+            }
+        }
+        None
+    }
+
+    /// Returns the position of the bracket matching the bracket `c` at `at`
+    fn matching_bracket(&self, at: &Position, c: char) -> Option<Position> {
+        let (direction, partner) = BRACKETS.iter().find_map(|&(open, close)| {
+            if c == open {
+                Some((SearchDirection::Forward, close))
+            } else if c == close {
+                Some((SearchDirection::Backward, open))
             } else {
-                self.rows.len()
+                None
             }
-        } else {
-            self.rows.len()
-        };
+        })?;
+        let mut depth: usize = 0;
+        for (position, other) in self.brackets_from(at, direction) {
+            if other == c {
+                depth = depth.saturating_add(1);
+            } else if other == partner {
+                if depth == 0 {
+                    return Some(position);
+                }
+                depth = depth.saturating_sub(1);
+            }
+        }
+        None
+    }
 
-        let rows_indexed = self.rows.get_mut(..until).expect("Failed while trying to index rows");
-        for row in rows_indexed {
-            start_with_comment = row.highlight(self.file_type.highlighting_options(), word, start_with_comment);
-        
+    /// Returns the brackets outside of strings and comments after `at`, or
+    /// before it going backward, up to `MAX_BRACKET_ROWS` rows away
+    fn brackets_from(&self, at: &Position, direction: SearchDirection) -> Vec<(Position, char)> {
+        let mut result = Vec::new();
+        if direction == SearchDirection::Forward {
+            for (y, row) in self.rows.iter().enumerate().skip(at.y).take(MAX_BRACKET_ROWS) {
+                for (x, c) in row.brackets() {
+                    if y > at.y || x > at.x {
+                        result.push((Position { x, y }, c));
+                    }
+                }
+            }
+        } else {
+            let rows = self.rows.iter().enumerate().take(at.y.saturating_add(1));
+            for (y, row) in rows.rev().take(MAX_BRACKET_ROWS) {
+                for (x, c) in row.brackets().into_iter().rev() {
+                    if y < at.y || x < at.x {
+                        result.push((Position { x, y }, c));
+                    }
+                }
+            }
         }
+        result
     }
 
     /// Returns a boolean indicating if the document has been changed or not
@@ -218,3 +933,206 @@ impl Document {
         None
     }
 }
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    fn rust_document(text: &str) -> Document {
+        let mut document = Document::default();
+        document.set_file_type(FileType::by_name("rust").expect("Rust is built in"));
+        let mut position = Position::default();
+        for c in text.chars() {
+            document.insert(&position, c);
+            if c == '\n' {
+                position = Position { x: 0, y: position.y.saturating_add(1) };
+            } else {
+                position.x = position.x.saturating_add(1);
+            }
+        }
+        document
+    }
+
+    fn end_states(document: &Document) -> Vec<Option<State>> {
+        document.rows.iter().map(Row::end_state).collect()
+    }
+
+    #[test]
+    fn test_incremental_highlight() {
+        let mut document = rust_document("let a;\n/* open\nstill\nclosed */\nlet b;");
+        document.highlight(&None, 0, document.len());
+        assert_eq!(
+            end_states(&document),
+            vec![
+                Some(State::Normal),
+                Some(State::BlockComment { depth: 1, doc: false }),
+                Some(State::BlockComment { depth: 1, doc: false }),
+                Some(State::Normal),
+                Some(State::Normal),
+            ]
+        );
+
+        // Only the edited row loses its highlighting, the rows below are
+        // checked again from it on.
+        document.delete(&Position { x: 0, y: 1 });
+        assert_eq!(document.highlighted_until, 1);
+        assert_eq!(document.row(2).and_then(Row::end_state), Some(State::BlockComment { depth: 1, doc: false }));
+        document.highlight(&None, 3, 5);
+        assert_eq!(
+            end_states(&document),
+            vec![Some(State::Normal); 5]
+        );
+        assert_eq!(document.highlighted_until, 5);
+    }
+
+    #[test]
+    fn test_bracket_pair() {
+        let mut document = rust_document("fn f() {\n    g(\"}\", '(');\n}");
+        let at = |x, y| Position { x, y };
+        assert_eq!(document.bracket_pair(&at(7, 0)), Some((at(7, 0), at(0, 2))));
+        assert_eq!(document.bracket_pair(&at(1, 2)), Some((at(0, 2), at(7, 0))));
+        // The brace in the string and the parenthesis in the character do
+        // not count.
+        assert_eq!(document.bracket_pair(&at(6, 1)), Some((at(5, 1), at(14, 1))));
+        assert_eq!(document.bracket_pair(&at(0, 1)), Some((at(7, 0), at(0, 2))));
+        assert_eq!(document.bracket_pair(&at(2, 0)), None);
+    }
+
+    #[test]
+    fn test_toggle_comment() {
+        let mut document = rust_document("    a\n\n        b\n  // c");
+        assert_eq!(document.toggle_comment(0, 2), Some(true));
+        let rows: Vec<&str> = document.rows.iter().map(Row::as_str).collect();
+        assert_eq!(rows, vec!["    // a", "", "    //     b", "  // c"]);
+        assert_eq!(document.toggle_comment(0, 3), Some(false));
+        let rows: Vec<&str> = document.rows.iter().map(Row::as_str).collect();
+        assert_eq!(rows, vec!["    a", "", "        b", "  c"]);
+
+        let mut document = Document::default();
+        document.insert(&Position::default(), 'x');
+        assert_eq!(document.toggle_comment(0, 0), None);
+    }
+
+    #[test]
+    fn test_indent_and_outdent() {
+        let mut document = rust_document("a\n\n\tb\n  c");
+        assert_eq!(document.indent(0, 3, "    "), vec![4, 0, 4, 4]);
+        let rows: Vec<&str> = document.rows.iter().map(Row::as_str).collect();
+        assert_eq!(rows, vec!["    a", "", "    \tb", "      c"]);
+        assert_eq!(document.outdent(0, 3, 4), vec![4, 0, 4, 4]);
+        assert_eq!(document.outdent(0, 3, 4), vec![0, 0, 1, 2]);
+        let rows: Vec<&str> = document.rows.iter().map(Row::as_str).collect();
+        assert_eq!(rows, vec!["a", "", "b", "c"]);
+    }
+
+    #[test]
+    fn test_row_operations() {
+        let rows = |document: &Document| -> Vec<String> {
+            document.rows.iter().map(|row| row.as_str().to_owned()).collect()
+        };
+        let mut document = rust_document("a\nb\nc");
+        assert_eq!(document.duplicate_rows(0, 1), 2);
+        assert_eq!(rows(&document), vec!["a", "b", "a", "b", "c"]);
+        document.delete_rows(1, 2);
+        assert_eq!(rows(&document), vec!["a", "b", "c"]);
+        assert!(document.move_rows(0, 1, SearchDirection::Forward));
+        assert_eq!(rows(&document), vec!["c", "a", "b"]);
+        assert!(!document.move_rows(1, 2, SearchDirection::Forward));
+        assert!(document.move_rows(2, 2, SearchDirection::Backward));
+        assert_eq!(rows(&document), vec!["c", "b", "a"]);
+        assert!(!document.move_rows(0, 0, SearchDirection::Backward));
+
+        let mut document = rust_document("f(\n    x,\n    y)");
+        assert_eq!(document.join_rows(0, 0), Some(2));
+        assert_eq!(rows(&document), vec!["f( x,", "    y)"]);
+        assert_eq!(document.join_rows(0, 1), Some(5));
+        assert_eq!(rows(&document), vec!["f( x, y)"]);
+        assert_eq!(document.join_rows(0, 0), None);
+    }
+
+    #[test]
+    fn test_insert_text() {
+        let mut document = rust_document("let a = ;");
+        let end = document.insert_text(&Position { x: 8, y: 0 }, "[\n    1,\n]");
+        assert_eq!((end.x, end.y), (1, 2));
+        assert_eq!(document.text(), "let a = [\n    1,\n];\n");
+        let end = document.insert_text(&Position { x: 0, y: 3 }, "x");
+        assert_eq!((end.x, end.y), (1, 3));
+        assert_eq!(document.len(), 4);
+    }
+
+    #[test]
+    fn test_changed_on_disk() {
+        let path = std::env::temp_dir().join(format!("hammare-disk-{}.txt", std::process::id()));
+        let file_name = path.to_string_lossy().into_owned();
+        fs::write(&path, "first\n").expect("written");
+        let mut document = Document::open(&file_name).expect("opened");
+        assert!(!document.changed_on_disk());
+        fs::write(&path, "changed outside\n").expect("written");
+        assert!(document.changed_on_disk());
+        document.record_disk_state();
+        assert!(!document.changed_on_disk());
+        fs::write(&path, "again, from elsewhere\n").expect("written");
+        document.insert(&Position::default(), 'x');
+        document.save().expect("saved");
+        let changed = document.changed_on_disk();
+        fs::remove_file(&path).expect("removed");
+        assert!(!changed);
+    }
+
+    #[test]
+    fn test_file_type_kept_on_save() {
+        let path = std::env::temp_dir().join(format!("hammare-filetype-{}.txt", std::process::id()));
+        let file_name = path.to_string_lossy().into_owned();
+        fs::write(&path, "# vim: ft=yaml\nkey: value\n").expect("written");
+        let mut document = Document::open(&file_name).expect("opened");
+        assert_eq!(document.file_type(), "YAML");
+        document.delete_rows(0, 0);
+        document.save().expect("saved");
+        assert_eq!(document.file_type(), "YAML");
+
+        let renamed = std::env::temp_dir().join(format!("hammare-filetype-{}.py", std::process::id()));
+        document.file_name = Some(renamed.to_string_lossy().into_owned());
+        document.save().expect("saved");
+        fs::remove_file(&path).expect("removed");
+        fs::remove_file(&renamed).expect("removed");
+        assert_eq!(document.file_type(), "Python");
+    }
+
+    #[test]
+    fn test_replace_range() {
+        let mut document = rust_document("one two\nthree\nfour five");
+        let (start, end) = (Position { x: 4, y: 0 }, Position { x: 4, y: 2 });
+        assert_eq!(document.text_between(&start, &end), "two\nthree\nfour");
+        assert_eq!(document.replace_range(&start, &end, "2\n3"), Position { x: 1, y: 1 });
+        assert_eq!(document.text(), "one 2\n3 five\n");
+        assert_eq!(document.replace_range(&Position { x: 1, y: 1 }, &Position { x: 6, y: 1 }, ""), Position { x: 1, y: 1 });
+        assert_eq!(document.text(), "one 2\n3\n");
+    }
+
+    #[test]
+    fn test_set_text() {
+        let mut document = rust_document("fn main() {\nlet  a = 1;\n}");
+        let version = document.version();
+        assert_eq!(document.set_text("fn main() {\n    let a = 1;\n    let b = 2;\n}\n"), Some((1, 2, 3)));
+        assert_eq!(document.text(), "fn main() {\n    let a = 1;\n    let b = 2;\n}\n");
+        assert!(document.version() > version);
+        assert_eq!(document.set_text("fn main() {\n    let a = 1;\n    let b = 2;\n}\n"), None);
+    }
+
+    #[test]
+    fn test_completions() {
+        let mut document = rust_document("let width = 1;\nlet wide_row = w\nwidget(width)");
+        let at = Position { x: 16, y: 1 };
+        let (start, prefix) = document.word_before(&at).expect("a word before the cursor");
+        assert_eq!((start.x, prefix.as_str()), (15, "w"));
+        assert_eq!(document.completions(&start, &prefix), vec!["wide_row", "width", "widget"]);
+        assert_eq!(document.word_before(&Position { x: 3, y: 0 }).map(|(_, word)| word), Some("let".to_owned()));
+        assert_eq!(document.word_before(&Position { x: 4, y: 0 }), None);
+
+        // Putting back the typed word leaves the document unchanged
+        let version = document.version();
+        assert_eq!(document.convert(&start, &at, |_| prefix.clone()), at);
+        assert_eq!(document.version(), version);
+    }
+}
@@ -1,17 +1,59 @@
+use crate::build::{self, Problem};
+use crate::completion::Completion;
+use crate::diff::{self, Change};
+use crate::filetype;
+use crate::git;
+use crate::highlighting;
+use crate::job::{self, Job};
+use crate::keymap::{self, Lookup};
+use crate::lsp;
+use crate::pairs;
+use crate::search::{self, Search};
+use crate::Action;
+use crate::Config;
 use crate::Document;
+use crate::FileType;
+use crate::Keymap;
+use crate::style::{Attributes, ColorDepth, Span, Style};
+use crate::theme::{self, Element};
+use crate::transform::{self, Case, SortOptions};
 use crate::Row;
 use crate::Terminal;
+use crate::Theme;
+use std::cmp;
 use std::env;
+use std::fs;
+use std::mem;
+use std::path::Path;
 use std::process;
 use std::time::Duration;
 use std::time::Instant;
-use termion::color;
 use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
 
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEFAULT_INDENT_WIDTH: usize = 4;
+/// Number of completion candidates shown at once
+const MAX_COMPLETIONS: usize = 8;
 const QUIT_TIMES: u8 = 3;
+/// How long to wait for a key before looking for language server messages
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often the file is checked for changes made outside of the editor
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How long the text must stay unchanged before the language server gets it
+/// and it is compared with its committed version
+const EDIT_PAUSE: Duration = Duration::from_millis(300);
+/// Width of the column showing the lines changed since the last commit and
+/// the diagnostics of the language server
+const GUTTER_WIDTH: usize = 3;
+/// Title of the scratch document listing the results of a project search
+const SEARCH_RESULTS_TITLE: &str = "[Search results]";
+/// Title of the scratch document showing the output of a shell command
+const OUTPUT_TITLE: &str = "[Output]";
+/// Title of the scratch document comparing a document with its file on disk
+const DIFF_TITLE: &str = "[Diff]";
+/// Number of unchanged lines shown around the changes of a diff
+const DIFF_CONTEXT: usize = 3;
 
 #[derive(PartialEq, Copy, Clone)]
 #[non_exhaustive]
@@ -20,7 +62,7 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
 #[non_exhaustive]
 pub struct Position {
     pub x: usize,
@@ -41,6 +83,37 @@ impl StatusMessage {
     }
 }
 
+/// A document that is open but not shown, with the state restored when it
+/// is shown again
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    diagnostics: Vec<lsp::Diagnostic>,
+    synced_version: usize,
+}
+
+impl Buffer {
+    fn from(document: Document) -> Self {
+        Self {
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            diagnostics: Vec::new(),
+            synced_version: 0,
+        }
+    }
+}
+
+/// A project search filling the search results document as it goes
+struct ProjectSearch {
+    search: Search,
+    /// The file of the last match listed
+    last_file: Option<String>,
+    matches: usize,
+    files: usize,
+}
+
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
@@ -49,21 +122,115 @@ pub struct Editor {
     document: Document,
     status_message: StatusMessage,
     quit_times: u8,
-    highlighted_word: Option<String>
+    highlighted_word: Option<String>,
+    keymap: Keymap,
+    pending_keys: Vec<Key>,
+    show_help: bool,
+    theme: Theme,
+    color_depth: ColorDepth,
+    /// The pair of brackets at or around the cursor, as of the last refresh
+    bracket_pair: Option<(Position, Position)>,
+    /// Where the selection started; it runs from there to the cursor
+    selection_anchor: Option<Position>,
+    auto_pairs: bool,
+    /// The text inserted by one level of indentation
+    indent_unit: String,
+    indent_width: usize,
+    /// The word completion in progress, shown in a popup
+    completion: Option<Completion>,
+    /// Closing characters inserted by auto-pairing on the cursor row, which
+    /// typing the same character moves over
+    auto_closed: Vec<Position>,
+    /// The documents open in the background
+    buffers: Vec<Buffer>,
+    /// The language server of the last file type that had one, if it runs
+    language_server: Option<lsp::Client>,
+    /// The command that started the language server
+    language_server_command: String,
+    /// The documents opened in the language server
+    opened_uris: Vec<String>,
+    /// The version of the document the language server last received
+    synced_version: usize,
+    /// The last version of the document seen and when it was first seen,
+    /// so that the text is only synced and compared when the typing pauses
+    last_edit: (usize, Instant),
+    /// The diagnostics of the document, as last reported by the server
+    diagnostics: Vec<lsp::Diagnostic>,
+    /// The command run by the `build` action without an argument
+    build_command: String,
+    /// Whether the formatter of the file type runs before saving
+    format_on_save: bool,
+    /// Whether a file changed on disk is read again, without asking, when
+    /// the document has no unsaved changes
+    auto_reload: bool,
+    /// Whether the number of each row is shown left of it
+    line_numbers: bool,
+    /// When to check next if the file changed on disk
+    next_disk_check: Instant,
+    /// Whether the change of the file on disk was already reported
+    disk_change_shown: bool,
+    /// The build running in the background, if one is
+    build: Option<Job>,
+    build_parser: build::Parser,
+    /// The errors and warnings of the last build
+    problems: Vec<Problem>,
+    /// The problem last jumped to
+    problem_index: Option<usize>,
+    project_search: Option<ProjectSearch>,
+    /// The shell command writing to the output document, with its command
+    /// line
+    command_job: Option<(Job, String)>,
 }
 
 impl Editor {
     /// Runs the editor
     pub fn run(&mut self) {
+        let mut refresh = true;
         loop {
-            if let Err(error) = self.refresh_screen() {
-                die(&error);
+            if refresh {
+                if let Err(error) = self.refresh_screen() {
+                    die(&error);
+                }
             }
             if self.should_quit {
                 break;
             }
-            if let Err(error) = self.process_keypress() {
-                die(&error);
+            refresh = match self.terminal.poll_key(EVENT_POLL_INTERVAL) {
+                Ok(Some(key)) => {
+                    if let Err(error) = self.process_keypress(key) {
+                        die(&error);
+                    }
+                    true
+                }
+                Ok(None) => false,
+                Err(error) => {
+                    die(&error);
+                    false
+                }
+            };
+            if self.edits_paused() {
+                self.sync_document();
+                if self.document.update_changes() {
+                    refresh = true;
+                }
+            }
+            if self.poll_language_server() {
+                refresh = true;
+            }
+            if self.poll_build() {
+                refresh = true;
+            }
+            if self.poll_project_search() {
+                refresh = true;
+            }
+            if self.poll_command() {
+                refresh = true;
+            }
+            if Instant::now() >= self.next_disk_check {
+                self.next_disk_check = Instant::now() + DISK_CHECK_INTERVAL;
+                if self.check_disk() {
+                    refresh = true;
+                }
             }
         }
     }
@@ -71,9 +238,98 @@ impl Editor {
     /// Generates a default `Editor` structure
     pub fn default() -> Self {
         let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from(
-            "HELP: Ctrl-F = find \u{1f50d} | Ctrl-S = save \u{1f916} | Ctrl-T = quit \u{2620}\u{fe0f}",
+        let mut keymap = Keymap::default();
+        let mut theme = Theme::default();
+        let mut color_depth = ColorDepth::detect();
+        let mut auto_pairs = true;
+        let mut indent_width = DEFAULT_INDENT_WIDTH;
+        let mut indent_with_tabs = false;
+        let mut build_command = build::DEFAULT_BUILD_COMMAND.to_owned();
+        let mut format_on_save = false;
+        let mut auto_reload = false;
+        let mut line_numbers = false;
+        let configured = Config::load().and_then(|config| {
+            keymap.configure(&config)?;
+            if let Some(name) = config.get("editor", "color_depth") {
+                color_depth = ColorDepth::from_name(name).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Unknown color depth `{name}`"),
+                    )
+                })?;
+            }
+            if let Some(value) = config.get("editor", "auto_pairs") {
+                auto_pairs = value.parse().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "`auto_pairs` must be `true` or `false`",
+                    )
+                })?;
+            }
+            if let Some(value) = config.get("editor", "indent_width") {
+                indent_width = value.parse().ok().filter(|&width| width > 0).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "`indent_width` must be a positive number",
+                    )
+                })?;
+            }
+            if let Some(value) = config.get("editor", "indent_style") {
+                indent_with_tabs = match value {
+                    "tabs" => true,
+                    "spaces" => false,
+                    _ => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "`indent_style` must be `spaces` or `tabs`",
+                        ))
+                    }
+                };
+            }
+            if let Some(value) = config.get("editor", "format_on_save") {
+                format_on_save = value.parse().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "`format_on_save` must be `true` or `false`",
+                    )
+                })?;
+            }
+            if let Some(value) = config.get("editor", "auto_reload") {
+                auto_reload = value.parse().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "`auto_reload` must be `true` or `false`",
+                    )
+                })?;
+            }
+            if let Some(value) = config.get("editor", "line_numbers") {
+                line_numbers = value.parse().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "`line_numbers` must be `true` or `false`",
+                    )
+                })?;
+            }
+            if let Some(command) = config.get("editor", "build_command") {
+                build_command = command.to_owned();
+            }
+            if let Some(name) = config.get("editor", "theme") {
+                theme = Theme::load(name)?;
+            }
+            filetype::languages()?;
+            Ok(())
+        });
+        let theme = theme.with_color_depth(color_depth);
+        let mut initial_status = format!(
+            "HELP: {} = find \u{1f50d} | {} = save \u{1f916} | {} = quit \u{2620}\u{fe0f} | {} = help",
+            bound_keys(&keymap, Action::Find),
+            bound_keys(&keymap, Action::Save),
+            bound_keys(&keymap, Action::Quit),
+            bound_keys(&keymap, Action::Help),
         );
+        if let Err(error) = configured {
+            initial_status = format!("ERR: Could not load configuration: {error}");
+        }
 
         let document = if let Some(file_name) = args.get(1) {
             let doc = Document::open(file_name);
@@ -87,7 +343,7 @@ impl Editor {
             Document::default()
         };
 
-        Self {
+        let mut editor = Self {
             should_quit: false,
             terminal: Terminal::default().expect("Failed to initialize terminal"),
             cursor_position: Position::default(),
@@ -96,7 +352,45 @@ impl Editor {
             status_message: StatusMessage::from(initial_status),
             quit_times: QUIT_TIMES,
             highlighted_word: None,
-        }
+            keymap,
+            pending_keys: Vec::new(),
+            show_help: false,
+            theme,
+            color_depth,
+            bracket_pair: None,
+            selection_anchor: None,
+            auto_pairs,
+            indent_unit: if indent_with_tabs {
+                "\t".to_owned()
+            } else {
+                " ".repeat(indent_width)
+            },
+            indent_width,
+            auto_closed: Vec::new(),
+            completion: None,
+            buffers: Vec::new(),
+            language_server: None,
+            language_server_command: String::new(),
+            opened_uris: Vec::new(),
+            synced_version: 0,
+            last_edit: (0, Instant::now()),
+            diagnostics: Vec::new(),
+            build_command,
+            format_on_save,
+            auto_reload,
+            line_numbers,
+            next_disk_check: Instant::now() + DISK_CHECK_INTERVAL,
+            disk_change_shown: false,
+            build: None,
+            build_parser: build::Parser::default(),
+            problems: Vec::new(),
+            problem_index: None,
+            project_search: None,
+            command_job: None,
+        };
+        editor.load_git_base();
+        editor.attach_language_server();
+        editor
     }
 
     #[allow(clippy::unwrap_in_result)]
@@ -107,15 +401,28 @@ impl Editor {
             Terminal::clear_screen();
             println!("May the force be with you \u{26a1}\u{fe0f}\r");
         } else {
-            self.document.highlight(&self.highlighted_word, Some(
-                self.offset.y.saturating_add(self.terminal.size().height.try_into().expect("Failed while trying to convert terminal size to usize")),
-            ),
-        );
+            let height: usize = self
+                .terminal
+                .size()
+                .height
+                .try_into()
+                .expect("Failed while trying to convert terminal size to usize");
+            self.document.highlight(
+                &self.highlighted_word,
+                self.offset.y,
+                self.offset.y.saturating_add(height),
+            );
+            self.bracket_pair = self.document.bracket_pair(&self.cursor_position);
             self.draw_rows();
             self.draw_status_bar();
             self.draw_message_bar();
+            self.draw_completion();
             Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
+                x: self
+                    .cursor_position
+                    .x
+                    .saturating_sub(self.offset.x)
+                    .saturating_add(self.gutter_width()),
                 y: self.cursor_position.y.saturating_sub(self.offset.y),
             });
         }
@@ -124,6 +431,10 @@ impl Editor {
     }
 
     fn save(&mut self) {
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from("The buffer is read-only".to_owned());
+            return;
+        }
         if self.document.file_name.is_none() {
             let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
             if new_name.is_none() {
@@ -133,8 +444,38 @@ impl Editor {
             self.document.file_name = new_name;
         }
 
+        if self.document.changed_on_disk() {
+            let answer = self
+                .ask("The file changed on disk: (o)verwrite it, (r)eload it, show the (d)iff?", &['o', 'r', 'd'])
+                .unwrap_or(None);
+            match answer {
+                Some('o') => (),
+                Some('r') => return self.reload(),
+                Some('d') => return self.show_diff(),
+                _ => {
+                    self.status_message = StatusMessage::from("Save aborted.".to_owned());
+                    return;
+                }
+            }
+        }
+        let mut formatting_error = None;
+        if self.format_on_save && self.document.formatter().is_some() {
+            formatting_error = self.run_formatter().err();
+        }
         if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully.".to_owned());
+            self.load_git_base();
+            self.status_message = StatusMessage::from(match formatting_error {
+                Some(error) => format!("ERR: File saved without formatting: {error}"),
+                None => "File saved successfully.".to_owned(),
+            });
+            if self.is_attached() {
+                self.sync_document();
+                if let Some(uri) = self.document_uri() {
+                    self.with_language_server(|server| server.did_save(&uri));
+                }
+            } else {
+                self.attach_language_server();
+            }
         } else {
             self.status_message = StatusMessage::from("Error writing file!".to_owned());
         }
@@ -194,15 +535,18 @@ impl Editor {
         };
 
         let mut file_name = "[No Name]".to_owned();
-        if let Some(ref name) = self.document.file_name {
+        if let Some(title) = self.document.title() {
+            file_name = title.to_owned();
+        } else if let Some(ref name) = self.document.file_name {
             file_name = name.clone();
             file_name.truncate(20);
         }
         let mut status = format!(
-            "{} - {} lines{}",
+            "{} - {} lines{}{}",
             file_name,
             self.document.len(),
-            modified_indicator
+            modified_indicator,
+            self.build_status()
         );
         let line_indicator = format!(
             "{} | {}/{}",
@@ -215,66 +559,86 @@ impl Editor {
         status.push_str(&" ".repeat(width.saturating_sub(len)));
         status = format!("{}{}", status, line_indicator);
         status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
+        Terminal::set_style(&self.theme.ui(Element::StatusBar));
         println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        Terminal::reset_style();
     }
 
+    /// Draws the status message while it is recent, or else the first
+    /// diagnostic of the cursor row
     fn draw_message_bar(&self) {
         Terminal::clear_current_line();
+        let width = self
+            .terminal
+            .size()
+            .width
+            .try_into()
+            .expect("Failed converting terminal size to usize");
         let message = &self.status_message;
         if Instant::now() - message.time < Duration::new(5, 0) {
             let mut text = message.text.clone();
-            text.truncate(
-                self.terminal
-                    .size()
-                    .width
-                    .try_into()
-                    .expect("Failed converting terminal size to usize"),
+            text.truncate(width);
+            Terminal::set_style(&self.theme.ui(Element::MessageBar));
+            print!("{}", text);
+            Terminal::reset_style();
+        } else if let Some(diagnostic) = self.row_diagnostic(self.cursor_position.y) {
+            let text = format!(
+                "{}: {}",
+                diagnostic.severity.marker(),
+                diagnostic.message.lines().next().unwrap_or_default()
             );
+            let text: String = text.graphemes(true).take(width).collect();
+            Terminal::set_style(&self.diagnostic_marker_style(diagnostic.severity));
             print!("{}", text);
+            Terminal::reset_style();
         }
     }
 
-    #[allow(clippy::string_slice)]
-    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
-        match pressed_key {
-            Key::Ctrl('t') => {
-                if self.quit_times > 0 && self.document.is_dirty() {
-                    self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-T {} more times to quit.",
-                        self.quit_times
-                    ));
-                    self.quit_times = self.quit_times.saturating_sub(1);
+    fn process_keypress(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        if self.show_help {
+            self.show_help = false;
+            return Ok(());
+        }
+        self.pending_keys.push(pressed_key);
+        match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Pending => {
+                self.status_message = StatusMessage::from(format!(
+                    "{}-",
+                    keymap::format_chord(&self.pending_keys)
+                ));
+                return Ok(());
+            }
+            Lookup::Action(action) => {
+                if self.pending_keys.len() > 1 {
+                    self.status_message = StatusMessage::from(String::new());
+                }
+                self.pending_keys.clear();
+                if !matches!(action, Action::CompleteNext | Action::CompletePrevious) {
+                    self.completion = None;
+                }
+                self.run_action(action, "");
+                if action == Action::Quit {
                     return Ok(());
                 }
-                self.should_quit = true;
             }
-            Key::Ctrl('s') => self.save(),
-            Key::Ctrl('f') => self.search(),
-            Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(Key::Right);
-            }
-            Key::Delete => self.document.delete(&self.cursor_position),
-            Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(Key::Left);
-                    self.document.delete(&self.cursor_position);
+            Lookup::Unbound => {
+                if self.completion.is_some() && self.pending_keys == [Key::Esc] {
+                    self.cancel_completion();
+                } else if self.document.is_read_only() && self.pending_keys == [Key::Char('\n')] {
+                    self.follow_link();
+                } else if self.document.is_read_only() && matches!(self.pending_keys[..], [Key::Char(_)]) {
+                    self.status_message = StatusMessage::from("The buffer is read-only".to_owned());
+                } else if let [Key::Char(c)] = self.pending_keys[..] {
+                    self.completion = None;
+                    self.insert_char(c);
+                } else if self.pending_keys.len() > 1 {
+                    self.status_message = StatusMessage::from(format!(
+                        "{} is not bound",
+                        keymap::format_chord(&self.pending_keys)
+                    ));
                 }
+                self.pending_keys.clear();
             }
-            Key::Up
-            | Key::Down
-            | Key::Left
-            | Key::Right
-            | Key::PageUp
-            | Key::PageDown
-            | Key::End
-            | Key::Home => self.move_cursor(pressed_key),
-            _ => (),
         }
         self.scroll();
         if self.quit_times < QUIT_TIMES {
@@ -284,177 +648,1877 @@ impl Editor {
         Ok(())
     }
 
-    fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let height = self
-            .terminal
-            .size()
-            .height
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let mut offset = &mut self.offset;
-
-        if y < offset.y {
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
+    /// Performs the editor operation named by `action`. Actions that need an
+    /// input use `argument`, or prompt for it when it is empty.
+    fn run_action(&mut self, action: Action, argument: &str) {
+        if action.is_edit() && self.document.is_read_only() {
+            self.status_message = StatusMessage::from("The buffer is read-only".to_owned());
+            return;
         }
-
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        match action {
+            Action::Quit => self.quit(),
+            Action::Save => self.save(),
+            Action::Find => self.search(),
+            Action::Help => self.show_help = true,
+            Action::MoveUp => self.move_cursor(Key::Up),
+            Action::MoveDown => self.move_cursor(Key::Down),
+            Action::MoveLeft => self.move_cursor(Key::Left),
+            Action::MoveRight => self.move_cursor(Key::Right),
+            Action::PageUp => self.move_cursor(Key::PageUp),
+            Action::PageDown => self.move_cursor(Key::PageDown),
+            Action::LineStart => self.move_cursor(Key::Home),
+            Action::LineEnd => self.move_cursor(Key::End),
+            Action::DeleteForward => {
+                self.selection_anchor = None;
+                self.document.delete(&self.cursor_position);
+            }
+            Action::DeleteBackward => self.delete_backward(),
+            Action::Command => self.command(),
+            Action::SwitchTheme => self.switch_theme(argument),
+            Action::SetFileType => self.set_file_type(argument),
+            Action::MatchBracket => self.match_bracket(),
+            Action::Select => self.toggle_selection(),
+            Action::ToggleComment => self.toggle_comment(),
+            Action::Tab => {
+                if self.selection_anchor.is_some() {
+                    self.indent();
+                } else {
+                    for c in self.indent_unit.clone().chars() {
+                        self.insert_char(c);
+                    }
+                }
+            }
+            Action::Indent => self.indent(),
+            Action::Outdent => self.outdent(),
+            Action::DuplicateLines => self.duplicate_lines(),
+            Action::DeleteLines => self.delete_lines(),
+            Action::MoveLinesUp => self.move_lines(SearchDirection::Backward),
+            Action::MoveLinesDown => self.move_lines(SearchDirection::Forward),
+            Action::JoinLines => self.join_lines(),
+            Action::SortLines => self.sort_lines(argument, false),
+            Action::UniqueLines => self.sort_lines(argument, true),
+            Action::ConvertCase => self.convert_case(argument),
+            Action::CompleteNext => self.complete(SearchDirection::Forward),
+            Action::CompletePrevious => self.complete(SearchDirection::Backward),
+            Action::Hover => self.request_at_cursor(lsp::Client::hover),
+            Action::GoToDefinition => self.request_at_cursor(lsp::Client::definition),
+            Action::LspComplete => self.request_at_cursor(lsp::Client::completion),
+            Action::Build => self.start_build(argument),
+            Action::NextError => self.go_to_problem(SearchDirection::Forward),
+            Action::PreviousError => self.go_to_problem(SearchDirection::Backward),
+            Action::SearchFiles => self.search_files(argument),
+            Action::NextBuffer => self.next_buffer(),
+            Action::CloseBuffer => self.close_buffer(),
+            Action::Format => self.format(),
+            Action::Filter => self.filter_lines(argument),
+            Action::InsertOutput => self.insert_output(argument),
+            Action::RunCommand => self.run_command(argument),
+            Action::KillCommand => self.kill_command(),
+            Action::NextChange => self.go_to_change(SearchDirection::Forward),
+            Action::PreviousChange => self.go_to_change(SearchDirection::Backward),
+            Action::ShowDiff => self.show_diff(),
+            Action::RevertHunk => self.revert_hunk(),
+            Action::Reload => self.reload(),
         }
     }
 
-    fn move_cursor(&mut self, key: Key) {
-        let terminal_height = self
-            .terminal
-            .size()
-            .height
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let Position { mut x, mut y } = self.cursor_position;
-        let height = self.document.len();
-        let mut width = if let Some(row) = self.document.row(y) {
-            row.len()
+    /// Runs a shell command, prompted for when missing, in the background.
+    /// Its output is shown in a scratch document as it is written.
+    fn run_command(&mut self, command: &str) {
+        let command = if command.trim().is_empty() {
+            match self.prompt("Run: ", |_, _, _| {}) {
+                Ok(Some(command)) => command,
+                _ => return,
+            }
         } else {
-            0
+            command.trim().to_owned()
         };
-        match key {
-            Key::Up => y = y.saturating_sub(1),
-            Key::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
+        self.command_job = None;
+        match Job::spawn(&command) {
+            Ok(job) => {
+                let mut output = Document::scratch(OUTPUT_TITLE);
+                output.push_line(&format!("$ {command}"), None);
+                self.show_scratch(output);
+                self.status_message = StatusMessage::from(format!("Running `{command}`"));
+                self.command_job = Some((job, command));
             }
-            Key::Left => {
-                if x > 0 {
-                    x = x.saturating_sub(1);
-                } else if y > 0 {
-                    y = y.saturating_sub(1);
-                    if let Some(row) = self.document.row(y) {
-                        x = row.len();
-                    } else {
-                        x = 0;
-                    }
-                }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not run `{command}`: {error}"));
             }
-            Key::Right => {
-                if x < width {
-                    x = x.saturating_add(1);
-                } else if y < height {
-                    y = y.saturating_add(1);
-                    x = 0;
-                }
+        }
+    }
+
+    /// Stops the running shell command, with the processes it started, and
+    /// ends its output with the lines it wrote until then
+    fn kill_command(&mut self) {
+        let Some((mut job, command)) = self.command_job.take() else {
+            self.status_message = StatusMessage::from("No command is running".to_owned());
+            return;
+        };
+        job.kill();
+        let mut lines = job.poll();
+        lines.push(String::new());
+        lines.push("[killed]".to_owned());
+        if let Some(output) = self.scratch_mut(OUTPUT_TITLE) {
+            for line in lines {
+                output.push_line(&line, None);
             }
-            Key::PageUp => {
-                y = if y > terminal_height {
-                    y.saturating_sub(terminal_height)
-                } else {
-                    0
+        }
+        self.status_message = StatusMessage::from(format!("Killed `{command}`"));
+    }
+
+    /// Appends the output of the running shell command to the output
+    /// document, following it when the cursor is on the last row, and tells
+    /// how it ended once it did. Returns whether the screen needs a refresh.
+    fn poll_command(&mut self) -> bool {
+        let Some((ref mut job, ref command)) = self.command_job else {
+            return false;
+        };
+        let mut lines = job.poll();
+        let status = job.status();
+        if lines.is_empty() && status.is_none() {
+            return false;
+        }
+        if let Some(status) = status {
+            lines.push(String::new());
+            lines.push(format!("[{status}]"));
+            self.status_message = StatusMessage::from(format!("`{command}` finished with {status}"));
+            self.command_job = None;
+        }
+        let shown = self.document.title() == Some(OUTPUT_TITLE);
+        let following = shown && self.cursor_position.y.saturating_add(1) >= self.document.len();
+        // The command stops when its output is closed
+        let Some(output) = self.scratch_mut(OUTPUT_TITLE) else {
+            self.command_job = None;
+            return false;
+        };
+        for line in lines {
+            output.push_line(&line, None);
+        }
+        if following {
+            let last = Position {
+                x: 0,
+                y: self.document.len().saturating_sub(1),
+            };
+            self.move_to(&last);
+        }
+        true
+    }
+
+    /// Pipes the lines of a range given first, the selected text, or the
+    /// whole document through a shell command, prompted for when missing,
+    /// and replaces them with its output. The text is left as it was when
+    /// the command fails.
+    fn filter_lines(&mut self, argument: &str) {
+        let argument = argument.trim();
+        let (first, rest) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+        let (range, command) = if parse_range(first).is_some() {
+            match self.line_range(first) {
+                Ok((range, _)) => (range, rest.trim()),
+                Err(error) => {
+                    self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                    return;
                 }
             }
-            Key::PageDown => {
-                y = if y.saturating_add(terminal_height) < height {
-                    y.saturating_add(terminal_height)
+        } else {
+            (None, argument)
+        };
+        let command = if command.is_empty() {
+            match self.prompt("Filter through: ", |_, _, _| {}) {
+                Ok(Some(command)) => command,
+                _ => return,
+            }
+        } else {
+            command.to_owned()
+        };
+        let selection = if range.is_none() { self.selection() } else { None };
+        let input = match (range, selection.as_ref()) {
+            (Some((start, end)), _) => self
+                .document
+                .lines(start, end)
+                .iter()
+                .map(|line| format!("{line}\n"))
+                .collect(),
+            (None, Some(&(ref start, ref end))) => self.document.text_between(start, end),
+            (None, None) => self.document.text(),
+        };
+        match job::filter(&command, &input) {
+            Ok(output) => {
+                if let Some((start, end)) = range {
+                    let lines: Vec<String> = output.lines().map(str::to_owned).collect();
+                    self.document.replace_rows(start, end, &lines);
+                    self.selection_anchor = None;
+                    let cursor = self.cursor_position.clone();
+                    self.move_to(&cursor);
+                } else if let Some((start, end)) = selection {
+                    // The rest of a row the selection ends in stays on it
+                    let output = if input.ends_with('\n') {
+                        output.as_str()
+                    } else {
+                        output.strip_suffix('\n').unwrap_or(&output)
+                    };
+                    self.selection_anchor = None;
+                    self.cursor_position = self.document.replace_range(&start, &end, output);
+                    self.scroll();
                 } else {
-                    height
+                    self.replace_text(&output);
                 }
+                self.status_message = StatusMessage::from(format!("Filtered through `{command}`"));
             }
-            Key::Home => x = 0,
-            Key::End => x = width,
-            _ => (),
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
         }
-        width = if let Some(row) = self.document.row(y) {
-            row.len()
+    }
+
+    /// Inserts the output of a shell command, prompted for when missing, at
+    /// the cursor, without its last newline
+    fn insert_output(&mut self, command: &str) {
+        let command = if command.trim().is_empty() {
+            match self.prompt("Insert output of: ", |_, _, _| {}) {
+                Ok(Some(command)) => command,
+                _ => return,
+            }
         } else {
-            0
+            command.trim().to_owned()
         };
-        if x > width {
-            x = width;
+        match job::filter(&command, "") {
+            Ok(output) => {
+                let output = output.strip_suffix('\n').unwrap_or(&output);
+                self.selection_anchor = None;
+                self.completion = None;
+                self.cursor_position = self.document.insert_text(&self.cursor_position, output);
+                self.scroll();
+            }
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
         }
-        self.cursor_position = Position { x, y }
     }
 
-    fn draw_welcome_message(&self) {
-        let mut welcome_message =
-            format!("Welcome to Hammare Editor \u{1f528} -- version {VERSION}");
-        let width: usize = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let len = welcome_message.len();
+    /// Formats the document with the formatter of its file type
+    fn format(&mut self) {
+        self.status_message = StatusMessage::from(match self.run_formatter() {
+            Ok(()) => "Formatted the file".to_owned(),
+            Err(error) => format!("ERR: {error}"),
+        });
+    }
 
-        let padding = width.saturating_sub(len) / 2;
+    /// Replaces the text with the output of the formatter of the file type,
+    /// given the text on its standard input
+    fn run_formatter(&mut self) -> Result<(), std::io::Error> {
+        let command = self.document.formatter().map(str::to_owned).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("No formatter for {}", self.document.file_type()),
+            )
+        })?;
+        let text = job::filter(&command, &self.document.text())?;
+        self.replace_text(&text);
+        Ok(())
+    }
 
-        let spaces = "  ".repeat(padding.saturating_sub(1));
-        welcome_message = format!("~{spaces}{welcome_message}");
-        welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+    /// Replaces the whole text of the document. The cursor stays on the
+    /// same text when its row only moved, and on a changed row after the
+    /// same number of non-blank characters.
+    fn replace_text(&mut self, text: &str) {
+        let Position { mut x, mut y } = self.cursor_position;
+        let non_blank_before = self.document.row(y).map_or(0, |row| {
+            row.as_str()
+                .graphemes(true)
+                .take(x)
+                .filter(|grapheme| !grapheme.trim().is_empty())
+                .count()
+        });
+        let Some((start, old_end, new_end)) = self.document.set_text(text) else {
+            return;
+        };
+        self.selection_anchor = None;
+        self.completion = None;
+        self.auto_closed.clear();
+        if y >= old_end {
+            y = y.saturating_sub(old_end).saturating_add(new_end);
+        } else if y >= start {
+            y = cmp::max(start, cmp::min(y, new_end.saturating_sub(1)));
+            x = self.document.row(y).map_or(0, |row| {
+                let mut non_blank = 0;
+                row.as_str()
+                    .graphemes(true)
+                    .take_while(|grapheme| {
+                        if non_blank == non_blank_before {
+                            return false;
+                        }
+                        if !grapheme.trim().is_empty() {
+                            non_blank = non_blank.saturating_add(1);
+                        }
+                        true
+                    })
+                    .count()
+            });
+        }
+        self.move_to(&Position { x, y });
     }
 
-    /// Draw a single row based on the cursor position
-    pub fn draw_row(&self, row: &Row) {
-        let width = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
+    /// Searches the files of the project, in the current directory, for
+    /// `query` or the text prompted for when it is empty. The results are
+    /// listed in a scratch document as they are found.
+    fn search_files(&mut self, query: &str) {
+        let query = if query.is_empty() {
+            match self.prompt("Search in files: ", |_, _, _| {}) {
+                Ok(Some(query)) => query,
+                _ => return,
+            }
+        } else {
+            query.to_owned()
+        };
+        let root = env::current_dir().unwrap_or_default();
+        let mut results = Document::scratch(SEARCH_RESULTS_TITLE);
+        results.push_line(&format!("Search results for `{query}` in {}", root.display()), None);
+        self.show_scratch(results);
+        self.project_search = Some(ProjectSearch {
+            search: Search::spawn(root, query),
+            last_file: None,
+            matches: 0,
+            files: 0,
+        });
     }
 
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self.document.row(
-                self.offset.y.saturating_add(
-                    terminal_row
-                        .try_into()
-                        .expect("Failed trying to convert terminal_row into usize"),
-                ),
-            ) {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
-            } else {
-                println!("~\r");
+    /// Lists the matches found by the project search since the last call,
+    /// grouped by file. Returns whether the screen needs a refresh.
+    fn poll_project_search(&mut self) -> bool {
+        let Some(project_search) = self.project_search.as_mut() else {
+            return false;
+        };
+        let matches = project_search.search.poll();
+        let done = project_search.search.is_done();
+        if matches.is_empty() && !done {
+            return false;
+        }
+        let mut lines: Vec<(String, Option<(String, Position)>)> = Vec::new();
+        for search::Match { file, line, column, text } in matches {
+            if project_search.last_file.as_ref() != Some(&file) {
+                lines.push((String::new(), None));
+                lines.push((file.clone(), None));
+                project_search.last_file = Some(file.clone());
+                project_search.files = project_search.files.saturating_add(1);
             }
+            project_search.matches = project_search.matches.saturating_add(1);
+            lines.push((
+                format!("{:>5}: {}", line.saturating_add(1), text.trim_start()),
+                Some((file, Position { x: column, y: line })),
+            ));
+        }
+        if done {
+            let summary = format!(
+                "{} matches in {} files",
+                project_search.matches, project_search.files
+            );
+            lines.push((String::new(), None));
+            lines.push((summary.clone(), None));
+            self.status_message = StatusMessage::from(summary);
+            self.project_search = None;
+        }
+        // The search stops when its results are closed
+        let Some(results) = self.scratch_mut(SEARCH_RESULTS_TITLE) else {
+            self.project_search = None;
+            return false;
+        };
+        for (text, link) in lines {
+            results.push_line(&text, link);
         }
+        true
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
-    where
-        C: FnMut(&mut Self, Key, &String),
-    {
-        let mut result = String::new();
-        loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
-            self.refresh_screen()?;
+    /// Opens the file and moves to the position the cursor row of a scratch
+    /// document refers to
+    fn follow_link(&mut self) {
+        let Some((file, position)) = self.document.link(self.cursor_position.y).cloned() else {
+            self.status_message = StatusMessage::from("Nothing to open on this line".to_owned());
+            return;
+        };
+        if self.open_file(&file) {
+            self.move_to(&position);
+        }
+    }
 
-            let key = Terminal::read_key()?;
+    /// Moves the cursor to `position`, kept within the document, and
+    /// scrolls to it
+    fn move_to(&mut self, position: &Position) {
+        let y = cmp::min(position.y, self.document.len());
+        let x = self.document.row(y).map_or(0, |row| cmp::min(position.x, row.len()));
+        self.cursor_position = Position { x, y };
+        self.scroll();
+    }
 
-            match key {
-                Key::Backspace => result.truncate(result.len().saturating_sub(1)),
-                Key::Char('\n') => break,
-                Key::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
-                    }
-                }
+    /// Returns the scratch document called `title`, shown or not
+    fn scratch_mut(&mut self, title: &str) -> Option<&mut Document> {
+        if self.document.title() == Some(title) {
+            return Some(&mut self.document);
+        }
+        self.buffers
+            .iter_mut()
+            .map(|buffer| &mut buffer.document)
+            .find(|document| document.title() == Some(title))
+    }
+
+    /// Shows a scratch document, replacing the one with the same title
+    fn show_scratch(&mut self, document: Document) {
+        self.buffers
+            .retain(|buffer| buffer.document.title().is_none() || buffer.document.title() != document.title());
+        if self.document.title().is_some() && self.document.title() == document.title() {
+            self.replace_buffer(Buffer::from(document));
+        } else {
+            self.switch_to(Buffer::from(document));
+        }
+    }
+
+    /// Shows `buffer`, keeping the current document open in the
+    /// background unless it is an untouched empty one
+    fn switch_to(&mut self, buffer: Buffer) {
+        let previous = self.replace_buffer(buffer);
+        let untouched = previous.document.file_name.is_none()
+            && !previous.document.is_read_only()
+            && !previous.document.is_dirty()
+            && previous.document.is_empty();
+        if !untouched {
+            self.buffers.push(previous);
+        }
+    }
+
+    /// Shows `buffer` in place of the current document, which is returned
+    fn replace_buffer(&mut self, buffer: Buffer) -> Buffer {
+        self.selection_anchor = None;
+        self.completion = None;
+        self.auto_closed.clear();
+        self.highlighted_word = None;
+        self.bracket_pair = None;
+        self.next_disk_check = Instant::now();
+        self.disk_change_shown = false;
+        self.sync_document();
+        let previous = Buffer {
+            document: mem::replace(&mut self.document, buffer.document),
+            cursor_position: mem::replace(&mut self.cursor_position, buffer.cursor_position),
+            offset: mem::replace(&mut self.offset, buffer.offset),
+            diagnostics: mem::replace(&mut self.diagnostics, buffer.diagnostics),
+            synced_version: mem::replace(&mut self.synced_version, buffer.synced_version),
+        };
+        self.attach_language_server();
+        previous
+    }
+
+    /// Shows the next document open in the background
+    fn next_buffer(&mut self) {
+        if self.buffers.is_empty() {
+            self.status_message = StatusMessage::from("No other buffer".to_owned());
+            return;
+        }
+        let buffer = self.buffers.remove(0);
+        self.switch_to(buffer);
+    }
+
+    /// Closes the current document, unless it has unsaved changes, and
+    /// shows the last one opened in the background
+    fn close_buffer(&mut self) {
+        if self.document.is_dirty() {
+            self.status_message =
+                StatusMessage::from("ERR: The file has unsaved changes, save it first".to_owned());
+            return;
+        }
+        if let Some(uri) = self.document_uri() {
+            if let Some(index) = self.opened_uris.iter().position(|opened| *opened == uri) {
+                self.opened_uris.remove(index);
+                self.with_language_server(|server| server.did_close(&uri));
+            }
+        }
+        let next = self
+            .buffers
+            .pop()
+            .unwrap_or_else(|| Buffer::from(Document::default()));
+        self.replace_buffer(next);
+    }
+
+    /// Saves the document if it has unsaved changes, then runs `command`,
+    /// or the configured build command when it is empty, in the background.
+    /// A build still running is stopped first.
+    fn start_build(&mut self, command: &str) {
+        let command = if command.is_empty() {
+            self.build_command.clone()
+        } else {
+            command.to_owned()
+        };
+        if self.document.is_dirty() && self.document.file_name.is_some() {
+            self.save();
+        }
+        self.build = None;
+        self.build_parser = build::Parser::default();
+        self.problems.clear();
+        self.problem_index = None;
+        match Job::spawn(&build::command_line(&command)) {
+            Ok(job) => {
+                self.build = Some(job);
+                self.status_message = StatusMessage::from(format!("Running `{command}`"));
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not run `{command}`: {error}"));
+            }
+        }
+    }
+
+    /// Collects the problems in the output of the running build, and tells
+    /// how it ended once it did. Returns whether the screen needs a refresh.
+    fn poll_build(&mut self) -> bool {
+        let Some(job) = self.build.as_mut() else {
+            return false;
+        };
+        let lines = job.poll();
+        let status = job.status();
+        let refresh = !lines.is_empty() || status.is_some();
+        for line in lines {
+            if let Some(problem) = self.build_parser.parse_line(&line) {
+                if !self.problems.contains(&problem) {
+                    self.problems.push(problem);
+                }
+            }
+        }
+        if let Some(status) = status {
+            self.build = None;
+            let outcome = if status.success() { "Build succeeded" } else { "Build failed" };
+            let summary = self.problem_summary();
+            self.status_message = StatusMessage::from(if summary.is_empty() {
+                outcome.to_owned()
+            } else {
+                format!("{outcome}: {summary}")
+            });
+        }
+        refresh
+    }
+
+    /// Counts the errors and the warnings of the last build
+    fn problem_summary(&self) -> String {
+        let errors = self
+            .problems
+            .iter()
+            .filter(|problem| problem.severity == lsp::Severity::Error)
+            .count();
+        let warnings = self
+            .problems
+            .iter()
+            .filter(|problem| problem.severity == lsp::Severity::Warning)
+            .count();
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        match (errors, warnings) {
+            (0, 0) => String::new(),
+            (errors, 0) => format!("{errors} error{}", plural(errors)),
+            (0, warnings) => format!("{warnings} warning{}", plural(warnings)),
+            (errors, warnings) => format!(
+                "{errors} error{}, {warnings} warning{}",
+                plural(errors),
+                plural(warnings)
+            ),
+        }
+    }
+
+    /// Describes the running build or the problems of the last one, for
+    /// the status bar
+    fn build_status(&self) -> String {
+        if self.build.is_some() {
+            " [building]".to_owned()
+        } else if self.problems.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", self.problem_summary())
+        }
+    }
+
+    /// Opens the file of the next or previous problem of the last build and
+    /// moves the cursor to it
+    fn go_to_problem(&mut self, direction: SearchDirection) {
+        if self.problems.is_empty() {
+            self.status_message = StatusMessage::from("No build errors".to_owned());
+            return;
+        }
+        let count = self.problems.len();
+        let index = match (self.problem_index, direction) {
+            (None, SearchDirection::Forward) => 0,
+            (None, SearchDirection::Backward) => count.saturating_sub(1),
+            (Some(index), SearchDirection::Forward) => index.saturating_add(1) % count,
+            (Some(index), SearchDirection::Backward) => index.checked_sub(1).unwrap_or(count.saturating_sub(1)),
+        };
+        let Some(problem) = self.problems.get(index).cloned() else {
+            return;
+        };
+        self.problem_index = Some(index);
+        if !self.open_file(&problem.file) {
+            return;
+        }
+        self.move_to(&Position {
+            x: problem.column.saturating_sub(1),
+            y: problem.line.saturating_sub(1),
+        });
+        self.status_message = StatusMessage::from(format!(
+            "{}/{count} {}: {}",
+            index.saturating_add(1),
+            if problem.severity == lsp::Severity::Error { "error" } else { "warning" },
+            problem.message
+        ));
+    }
+
+    /// Reports once that the file changed on disk, leaving the choice of
+    /// what to do to the next save, or reads it again right away if it has
+    /// no unsaved changes and `auto_reload` is set. Returns whether the
+    /// screen needs a refresh.
+    fn check_disk(&mut self) -> bool {
+        if !self.document.changed_on_disk() {
+            self.disk_change_shown = false;
+            return false;
+        }
+        if self.auto_reload && !self.document.is_dirty() {
+            self.reload();
+            return true;
+        }
+        if self.disk_change_shown {
+            return false;
+        }
+        self.disk_change_shown = true;
+        self.status_message =
+            StatusMessage::from("The file changed on disk: `reload` reads it again, saving asks first".to_owned());
+        true
+    }
+
+    /// Reads the file again, dropping the unsaved changes
+    fn reload(&mut self) {
+        let Some(file_name) = self.document.file_name.clone().filter(|_| !self.document.is_read_only()) else {
+            self.status_message = StatusMessage::from("The buffer is not a file".to_owned());
+            return;
+        };
+        match fs::read_to_string(&file_name) {
+            Ok(text) => {
+                self.replace_text(&text);
+                self.document.mark_saved();
+                self.load_git_base();
+                self.status_message = StatusMessage::from(format!("Reloaded {file_name}"));
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not reload {file_name}: {error}"));
+            }
+        }
+    }
+
+    /// Returns the lines of the file as saved on disk, none if it was not
+    /// saved yet
+    fn saved_lines(&self) -> Result<Vec<String>, std::io::Error> {
+        let file_name = self.document.file_name.as_ref().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "The buffer is not a file")
+        })?;
+        match fs::read_to_string(file_name) {
+            Ok(text) => Ok(text.lines().map(str::to_owned).collect()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Shows the differences between the file on disk and the document as
+    /// a unified diff, in a scratch document whose lines lead to the text
+    /// they show
+    fn show_diff(&mut self) {
+        let saved = match self.saved_lines() {
+            Ok(saved) => saved,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                return;
+            }
+        };
+        let file_name = self.document.file_name.clone().unwrap_or_default();
+        let lines = diff::unified(&saved, &self.document.lines(0, self.document.len()), DIFF_CONTEXT);
+        if lines.is_empty() {
+            self.buffers.retain(|buffer| buffer.document.title() != Some(DIFF_TITLE));
+            self.status_message = StatusMessage::from("No difference with the file on disk".to_owned());
+            return;
+        }
+        let mut document = Document::scratch(DIFF_TITLE);
+        if let Some(file_type) = FileType::by_name("diff") {
+            document.set_file_type(file_type);
+        }
+        document.push_line(&format!("--- {file_name} (on disk)"), None);
+        document.push_line(&format!("+++ {file_name} (edited)"), None);
+        for (line, row) in lines {
+            document.push_line(&line, Some((file_name.clone(), Position { x: 0, y: row })));
+        }
+        self.show_scratch(document);
+    }
+
+    /// Puts back the saved version of the lines changed around the cursor.
+    /// In the diff with the file on disk, the hunk at the cursor is
+    /// reverted in its file and the diff is shown again.
+    fn revert_hunk(&mut self) {
+        if self.document.title() == Some(DIFF_TITLE) {
+            let Some((file, position)) = self.document.link(self.cursor_position.y).cloned() else {
+                self.status_message = StatusMessage::from("No change on this line".to_owned());
+                return;
+            };
+            let diff_row = self.cursor_position.y;
+            if !self.open_file(&file) {
+                return;
+            }
+            self.move_to(&position);
+            self.revert_hunk();
+            let reverted = mem::replace(&mut self.status_message, StatusMessage::from(String::new()));
+            self.show_diff();
+            if self.document.title() == Some(DIFF_TITLE) {
+                self.move_to(&Position { x: 0, y: diff_row });
+            }
+            self.status_message = reverted;
+            return;
+        }
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from("The buffer is read-only".to_owned());
+            return;
+        }
+        let saved = match self.saved_lines() {
+            Ok(saved) => saved,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                return;
+            }
+        };
+        let rows = self.document.lines(0, self.document.len());
+        let y = self.cursor_position.y;
+        let hunks = diff::diff(&saved, &rows);
+        // Deleted lines are reverted from the rows around them
+        let Some(hunk) = hunks.iter().find(|hunk| {
+            if hunk.new_len == 0 {
+                y == hunk.new_start || y.saturating_add(1) == hunk.new_start
+            } else {
+                (hunk.new_start..hunk.new_start.saturating_add(hunk.new_len)).contains(&y)
+            }
+        }) else {
+            self.status_message = StatusMessage::from("No unsaved change at the cursor".to_owned());
+            return;
+        };
+        let text: String = rows
+            .iter()
+            .take(hunk.new_start)
+            .chain(saved.iter().skip(hunk.old_start).take(hunk.old_len))
+            .chain(rows.iter().skip(hunk.new_start.saturating_add(hunk.new_len)))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        self.replace_text(&text);
+        self.status_message = StatusMessage::from(format!(
+            "Reverted {} saved lines in place of {}",
+            hunk.old_len, hunk.new_len
+        ));
+    }
+
+    /// Reads the committed version of the file, to show the changes made
+    /// since
+    fn load_git_base(&mut self) {
+        let base = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|file_name| git::head_text(Path::new(file_name)));
+        self.document.set_base(base);
+    }
+
+    /// Moves to the next or the previous hunk changed since the last
+    /// commit, going around at the end of the document
+    fn go_to_change(&mut self, direction: SearchDirection) {
+        if !self.document.has_base() {
+            self.status_message = StatusMessage::from("The file is not committed to git".to_owned());
+            return;
+        }
+        self.document.update_changes();
+        let rows: Vec<usize> = self
+            .document
+            .changes()
+            .iter()
+            .map(|hunk| {
+                if hunk.change() == Change::Deleted {
+                    hunk.new_start.saturating_sub(1)
+                } else {
+                    hunk.new_start
+                }
+            })
+            .collect();
+        let y = self.cursor_position.y;
+        let row = match direction {
+            SearchDirection::Forward => rows.iter().find(|&&row| row > y).or_else(|| rows.first()),
+            SearchDirection::Backward => rows.iter().rev().find(|&&row| row < y).or_else(|| rows.last()),
+        };
+        let Some(&row) = row else {
+            self.status_message = StatusMessage::from("No changes since the last commit".to_owned());
+            return;
+        };
+        let index = rows.iter().position(|&start| start == row).unwrap_or_default();
+        self.move_to(&Position { x: 0, y: row });
+        self.status_message = StatusMessage::from(format!("Change {}/{}", index.saturating_add(1), rows.len()));
+    }
+
+    /// Returns the URI of the document for the language server, once it
+    /// has a file name
+    fn document_uri(&self) -> Option<String> {
+        self.document
+            .file_name
+            .as_ref()
+            .map(|file_name| lsp::path_to_uri(Path::new(file_name)))
+    }
+
+    /// Opens the document in the language server of its file type,
+    /// starting it in place of the running one if that one is another
+    /// server. Documents without a server leave the running one alone.
+    fn attach_language_server(&mut self) {
+        let (Some(command), Some(uri)) = (self.document.language_server(), self.document_uri()) else {
+            return;
+        };
+        if self.language_server.is_some() && command == self.language_server_command {
+            if !self.opened_uris.contains(&uri) {
+                self.open_in_language_server(&uri);
+            }
+            return;
+        }
+        self.start_language_server();
+    }
+
+    /// Checks if the document is open in the running language server
+    fn is_attached(&self) -> bool {
+        self.language_server.is_some()
+            && self.document.language_server() == Some(self.language_server_command.as_str())
+            && self
+                .document_uri()
+                .map_or(false, |uri| self.opened_uris.contains(&uri))
+    }
+
+    /// Starts the language server configured for the file type of the
+    /// document, replacing the running one, and opens the document in it
+    fn start_language_server(&mut self) {
+        self.language_server = None;
+        self.opened_uris.clear();
+        self.diagnostics.clear();
+        for buffer in &mut self.buffers {
+            buffer.diagnostics.clear();
+        }
+        let (Some(command), Some(uri)) = (self.document.language_server(), self.document_uri()) else {
+            return;
+        };
+        let command = command.to_owned();
+        let root = env::current_dir().unwrap_or_default();
+        match lsp::Client::spawn(&command, &root) {
+            Ok(server) => {
+                self.language_server = Some(server);
+                self.language_server_command = command;
+                self.open_in_language_server(&uri);
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!(
+                    "ERR: Could not start language server `{command}`: {error}"
+                ));
+            }
+        }
+    }
+
+    /// Sends the whole document to the language server as opened at `uri`
+    fn open_in_language_server(&mut self, uri: &str) {
+        let language_id = self.document.language_id();
+        let version = self.document.version();
+        let text = self.document.text();
+        self.with_language_server(|server| server.did_open(uri, &language_id, version, &text));
+        self.synced_version = version;
+        if self.language_server.is_some() {
+            self.opened_uris.push(uri.to_owned());
+        }
+    }
+
+    /// Calls `send` with the language server, if one is running. The
+    /// server is stopped if it cannot be written to anymore.
+    fn with_language_server<F>(&mut self, send: F)
+    where
+        F: FnOnce(&mut lsp::Client) -> Result<(), std::io::Error>,
+    {
+        if let Some(server) = self.language_server.as_mut() {
+            if let Err(error) = send(server) {
+                self.language_server = None;
+                self.opened_uris.clear();
+                self.diagnostics.clear();
+                self.status_message =
+                    StatusMessage::from(format!("ERR: The language server stopped: {error}"));
+            }
+        }
+    }
+
+    /// Sends the text of the document to the language server if it changed
+    /// since the server last got it
+    fn sync_document(&mut self) {
+        let version = self.document.version();
+        if !self.is_attached() || version == self.synced_version {
+            return;
+        }
+        if let Some(uri) = self.document_uri() {
+            let text = self.document.text();
+            self.with_language_server(|server| server.did_change(&uri, version, &text));
+        }
+        self.synced_version = version;
+    }
+
+    /// Checks if the text stayed unchanged for `EDIT_PAUSE`
+    fn edits_paused(&mut self) -> bool {
+        let version = self.document.version();
+        if version != self.last_edit.0 {
+            self.last_edit = (version, Instant::now());
+            return false;
+        }
+        self.last_edit.1.elapsed() >= EDIT_PAUSE
+    }
+
+    /// Handles what the language server sent since the last call. Returns
+    /// whether the screen needs a refresh.
+    fn poll_language_server(&mut self) -> bool {
+        let events = self
+            .language_server
+            .as_mut()
+            .map(lsp::Client::poll)
+            .unwrap_or_default();
+        let refresh = !events.is_empty();
+        for event in events {
+            self.handle_language_server_event(event);
+        }
+        refresh
+    }
+
+    fn handle_language_server_event(&mut self, event: lsp::Event) {
+        match event {
+            lsp::Event::Diagnostics { uri, mut diagnostics } => {
+                diagnostics.sort_by_key(|diagnostic| (diagnostic.start.line, diagnostic.severity));
+                if Some(&uri) == self.document_uri().as_ref() {
+                    self.diagnostics = diagnostics;
+                } else if let Some(buffer) = self.buffers.iter_mut().find(|buffer| {
+                    buffer
+                        .document
+                        .file_name
+                        .as_ref()
+                        .map_or(false, |file_name| lsp::path_to_uri(Path::new(file_name)) == uri)
+                }) {
+                    buffer.diagnostics = diagnostics;
+                }
+            }
+            lsp::Event::Hover(text) => {
+                let text = text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with("```") && *line != "---")
+                    .collect::<Vec<&str>>()
+                    .join(" ");
+                self.status_message = StatusMessage::from(text);
+            }
+            lsp::Event::Definition { uri, position } => self.go_to(&uri, position),
+            lsp::Event::Completion(candidates) => {
+                let cursor = self.cursor_position.clone();
+                let (start, prefix) = self
+                    .document
+                    .word_before(&cursor)
+                    .unwrap_or((cursor, String::new()));
+                let mut candidates: Vec<String> = candidates
+                    .into_iter()
+                    .filter(|candidate| candidate.starts_with(&prefix) && *candidate != prefix)
+                    .collect();
+                candidates.dedup();
+                self.completion = Completion::new(start, prefix, candidates);
+                if self.completion.is_none() {
+                    self.status_message = StatusMessage::from("No completions".to_owned());
+                } else {
+                    self.complete(SearchDirection::Forward);
+                }
+            }
+            lsp::Event::Error(message) => {
+                self.status_message = StatusMessage::from(format!("ERR: {message}"));
+            }
+        }
+    }
+
+    /// Sends a request about the symbol at the cursor to the language
+    /// server, whose response is handled when it arrives
+    fn request_at_cursor(
+        &mut self,
+        request: fn(&mut lsp::Client, &str, lsp::Position) -> Result<(), std::io::Error>,
+    ) {
+        let Some(uri) = self.document_uri().filter(|_| self.is_attached()) else {
+            self.status_message = StatusMessage::from("No language server".to_owned());
+            return;
+        };
+        self.sync_document();
+        let position = self.document.lsp_position(&self.cursor_position);
+        self.with_language_server(|server| request(server, &uri, position));
+    }
+
+    /// Moves the cursor to `position` in the document at `uri`, opening
+    /// that document unless it is the current one. The current document
+    /// must be saved first.
+    fn go_to(&mut self, uri: &str, position: lsp::Position) {
+        let Some(path) = lsp::uri_to_path(uri) else {
+            self.status_message = StatusMessage::from(format!("ERR: Cannot open {uri}"));
+            return;
+        };
+        if self.open_file(&path.to_string_lossy()) {
+            let position = self.document.position_from_lsp(position);
+            self.move_to(&position);
+        }
+    }
+
+    /// Shows `file_name`, opening it unless it is open already. Returns
+    /// whether it is the document now.
+    fn open_file(&mut self, file_name: &str) -> bool {
+        let is_file = |document: &Document| {
+            document
+                .file_name
+                .as_ref()
+                .map_or(false, |current| same_file(current, file_name))
+        };
+        if is_file(&self.document) {
+            return true;
+        }
+        if let Some(index) = self.buffers.iter().position(|buffer| is_file(&buffer.document)) {
+            let buffer = self.buffers.remove(index);
+            self.switch_to(buffer);
+            return true;
+        }
+        match Document::open(file_name) {
+            Ok(document) => {
+                self.switch_to(Buffer::from(document));
+                self.load_git_base();
+                true
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not open {file_name}: {error}"));
+                false
+            }
+        }
+    }
+
+    /// Returns the width of the columns in front of the rows: the row
+    /// numbers, if shown, and the markers
+    fn gutter_width(&self) -> usize {
+        let markers = if self.is_attached() || self.document.has_base() {
+            GUTTER_WIDTH
+        } else {
+            0
+        };
+        markers.saturating_add(self.line_number_width())
+    }
+
+    /// Returns the width of the row numbers followed by a space, or 0 when
+    /// they are not shown
+    fn line_number_width(&self) -> usize {
+        if self.line_numbers {
+            cmp::max(self.document.len(), 1).to_string().len().saturating_add(1)
+        } else {
+            0
+        }
+    }
+
+    /// Returns the width left to the text of the rows
+    fn text_width(&self) -> usize {
+        let width: usize = self
+            .terminal
+            .size()
+            .width
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        width.saturating_sub(self.gutter_width())
+    }
+
+    /// Returns the most severe diagnostic starting on the row at `index`
+    fn row_diagnostic(&self, index: usize) -> Option<&lsp::Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.start.line == index)
+            .min_by_key(|diagnostic| diagnostic.severity)
+    }
+
+    /// Returns how the row at `index` changed since the last commit. Lines
+    /// deleted below a row are marked on it.
+    fn row_change(&self, index: usize) -> Option<Change> {
+        self.document.changes().iter().find_map(|hunk| {
+            let change = hunk.change();
+            let marked = if change == Change::Deleted {
+                hunk.new_start.saturating_sub(1) == index
+            } else {
+                (hunk.new_start..hunk.new_start.saturating_add(hunk.new_len)).contains(&index)
+            };
+            marked.then_some(change)
+        })
+    }
+
+    /// Returns the style of the underline of a diagnostic
+    fn diagnostic_style(&self, severity: lsp::Severity) -> Style {
+        self.theme.ui(match severity {
+            lsp::Severity::Error => Element::DiagnosticError,
+            lsp::Severity::Warning => Element::DiagnosticWarning,
+            lsp::Severity::Information | lsp::Severity::Hint => Element::DiagnosticInfo,
+        })
+    }
+
+    /// Returns the style of the gutter marker and of the message of a
+    /// diagnostic: its underline style, without the underline
+    fn diagnostic_marker_style(&self, severity: lsp::Severity) -> Style {
+        Style {
+            attributes: Attributes::default(),
+            ..self.diagnostic_style(severity)
+        }
+    }
+
+    /// Completes the word before the cursor with the next or previous word
+    /// of the document starting the same way, the closest ones first
+    fn complete(&mut self, direction: SearchDirection) {
+        if self.completion.is_none() {
+            let Some((start, prefix)) = self.document.word_before(&self.cursor_position) else {
+                self.status_message = StatusMessage::from("Nothing to complete".to_owned());
+                return;
+            };
+            let candidates = self.document.completions(&start, &prefix);
+            self.completion = Completion::new(start, prefix, candidates);
+            if self.completion.is_none() {
+                self.status_message = StatusMessage::from("No completions".to_owned());
+                return;
+            }
+        }
+        if let Some(completion) = self.completion.as_mut() {
+            if direction == SearchDirection::Forward {
+                completion.select_next();
+            } else {
+                completion.select_previous();
+            }
+        }
+        self.insert_completion();
+    }
+
+    /// Replaces the word being completed with the selected candidate
+    fn insert_completion(&mut self) {
+        if let Some(completion) = self.completion.as_ref() {
+            let text = completion.text().to_owned();
+            self.cursor_position = self
+                .document
+                .convert(completion.start(), &self.cursor_position, |_| text.clone());
+        }
+    }
+
+    /// Stops completing and puts back the word as it was typed
+    fn cancel_completion(&mut self) {
+        if let Some(completion) = self.completion.as_mut() {
+            while completion.selected().is_some() {
+                completion.select_next();
+            }
+        }
+        self.insert_completion();
+        self.completion = None;
+    }
+
+    /// Inserts a typed character. With auto-pairing, an opening character
+    /// also gets its closing one after the cursor, and typing a closing
+    /// character that was inserted that way moves over it.
+    fn insert_char(&mut self, c: char) {
+        self.selection_anchor = None;
+        let at = self.cursor_position.clone();
+        self.auto_closed.retain(|closed| closed.y == at.y);
+        if let Some(index) = self.auto_closed.iter().position(|closed| *closed == at) {
+            if self.document.char_at(&at) == Some(c) {
+                self.auto_closed.remove(index);
+                self.move_cursor(Key::Right);
+                return;
+            }
+        }
+        let closing = if self.auto_pairs {
+            let before = at.x.checked_sub(1).map(|x| Position { x, y: at.y });
+            let in_string_or_comment = before
+                .as_ref()
+                .and_then(|before| self.document.row(before.y)?.highlighting_at(before.x))
+                .map_or(false, highlighting::Type::is_string_or_comment);
+            pairs::closing(
+                c,
+                self.document.highlighting_options(),
+                before.and_then(|before| self.document.char_at(&before)),
+                self.document.char_at(&at),
+                in_string_or_comment,
+            )
+        } else {
+            None
+        };
+        self.document.insert(&at, c);
+        self.shift_auto_closed(&at, true);
+        self.move_cursor(Key::Right);
+        if let Some(closing) = closing {
+            self.document.insert(&self.cursor_position, closing);
+            self.shift_auto_closed(&self.cursor_position.clone(), true);
+            self.auto_closed.push(self.cursor_position.clone());
+        }
+    }
+
+    /// Deletes the character before the cursor, along with the closing
+    /// character right after it when the two form an empty pair
+    fn delete_backward(&mut self) {
+        self.selection_anchor = None;
+        if self.cursor_position.x == 0 && self.cursor_position.y == 0 {
+            return;
+        }
+        let after = self.document.char_at(&self.cursor_position);
+        self.move_cursor(Key::Left);
+        let at = self.cursor_position.clone();
+        let empty_pair = match (self.document.char_at(&at), after) {
+            (Some(open), Some(close)) => {
+                self.auto_pairs && pairs::is_pair(open, close, self.document.highlighting_options())
+            }
+            _ => false,
+        };
+        self.document.delete(&at);
+        self.shift_auto_closed(&at, false);
+        if empty_pair {
+            self.document.delete(&at);
+            self.shift_auto_closed(&at, false);
+        }
+    }
+
+    /// Keeps the auto-inserted closing characters in place after a
+    /// character was inserted or deleted at `at`
+    fn shift_auto_closed(&mut self, at: &Position, inserted: bool) {
+        self.auto_closed.retain(|closed| closed.y == at.y && (inserted || closed.x != at.x));
+        for closed in self.auto_closed.iter_mut().filter(|closed| closed.x >= at.x) {
+            closed.x = if inserted {
+                closed.x.saturating_add(1)
+            } else {
+                closed.x.saturating_sub(1)
+            };
+        }
+    }
+
+    fn quit(&mut self) {
+        let dirty = self.document.is_dirty() || self.buffers.iter().any(|buffer| buffer.document.is_dirty());
+        if self.quit_times > 0 && dirty {
+            self.status_message = StatusMessage::from(format!(
+                "WARNING! File has unsaved changes. Press {} {} more times to quit.",
+                bound_keys(&self.keymap, Action::Quit),
+                self.quit_times
+            ));
+            self.quit_times = self.quit_times.saturating_sub(1);
+            return;
+        }
+        self.language_server = None;
+        self.build = None;
+        self.project_search = None;
+        self.command_job = None;
+        self.should_quit = true;
+    }
+
+    /// Prompts for an action name followed by its argument and runs it
+    fn command(&mut self) {
+        let command = self.prompt("Command: ", |_, _, _| {}).unwrap_or(None);
+        if let Some(command) = command {
+            let (name, argument) = command.split_once(' ').unwrap_or((&command, ""));
+            if let Some(action) = Action::from_name(name) {
+                self.run_action(action, argument.trim());
+            } else {
+                self.status_message = StatusMessage::from(format!("Unknown command: {name}"));
+            }
+        }
+    }
+
+    /// Starts a selection at the cursor, or clears the current one
+    fn toggle_selection(&mut self) {
+        if self.selection_anchor.take().is_none() {
+            self.selection_anchor = Some(self.cursor_position.clone());
+            self.status_message = StatusMessage::from("Selection started".to_owned());
+        }
+    }
+
+    /// Returns the start and the end of the selection, in document order
+    fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection_anchor.clone()?;
+        let cursor = self.cursor_position.clone();
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    /// Returns the first and last rows of the selection, or the cursor row
+    /// without one. A selection ending at the start of a row does not
+    /// include that row.
+    fn selected_rows(&self) -> (usize, usize) {
+        match self.selection() {
+            Some((start, end)) if end.x == 0 && end.y > start.y => (start.y, end.y.saturating_sub(1)),
+            Some((start, end)) => (start.y, end.y),
+            None => (self.cursor_position.y, self.cursor_position.y),
+        }
+    }
+
+    fn toggle_comment(&mut self) {
+        let (start, end) = self.selected_rows();
+        if self.document.toggle_comment(start, end).is_none() {
+            self.status_message = StatusMessage::from(format!(
+                "ERR: {} has no line comments",
+                self.document.file_type()
+            ));
+            return;
+        }
+        let width = self.document.row(self.cursor_position.y).map_or(0, Row::len);
+        self.cursor_position.x = cmp::min(self.cursor_position.x, width);
+    }
+
+    fn indent(&mut self) {
+        let (start, end) = self.selected_rows();
+        let added = self.document.indent(start, end, &self.indent_unit);
+        self.shift_selection(start, &added, true);
+    }
+
+    fn outdent(&mut self) {
+        let (start, end) = self.selected_rows();
+        let removed = self.document.outdent(start, end, self.indent_width);
+        self.shift_selection(start, &removed, false);
+    }
+
+    /// Duplicates the current or selected lines, leaving the cursor and the
+    /// selection on the copy
+    fn duplicate_lines(&mut self) {
+        let (start, end) = self.selected_rows();
+        let count = self.document.duplicate_rows(start, end);
+        self.shift_rows(count, SearchDirection::Forward);
+    }
+
+    fn delete_lines(&mut self) {
+        let (start, end) = self.selected_rows();
+        self.document.delete_rows(start, end);
+        self.selection_anchor = None;
+        self.cursor_position.y = cmp::min(start, self.document.len());
+        let width = self.document.row(self.cursor_position.y).map_or(0, Row::len);
+        self.cursor_position.x = cmp::min(self.cursor_position.x, width);
+    }
+
+    fn move_lines(&mut self, direction: SearchDirection) {
+        let (start, end) = self.selected_rows();
+        if self.document.move_rows(start, end, direction) {
+            self.shift_rows(1, direction);
+        }
+    }
+
+    fn join_lines(&mut self) {
+        let (start, end) = self.selected_rows();
+        if let Some(x) = self.document.join_rows(start, end) {
+            self.selection_anchor = None;
+            self.cursor_position = Position { x, y: start };
+        }
+    }
+
+    /// Sorts the lines, or removes the duplicate ones if `unique` is set.
+    /// The `argument` may start with a line range, followed by the sort
+    /// options.
+    fn sort_lines(&mut self, argument: &str, unique: bool) {
+        let result = self.line_range(argument).and_then(|(range, options)| {
+            let (start, end) = range.unwrap_or_else(|| self.selected_rows());
+            let mut lines = self.document.lines(start, end);
+            if unique {
+                transform::unique_lines(&mut lines);
+            } else {
+                transform::sort_lines(&mut lines, SortOptions::parse(options)?);
+            }
+            self.document.replace_rows(start, end, &lines);
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                self.selection_anchor = None;
+                let width = self.document.row(self.cursor_position.y).map_or(0, Row::len);
+                self.cursor_position.x = cmp::min(self.cursor_position.x, width);
+            }
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
+        }
+    }
+
+    /// Converts the case of the selected text, or of the lines of a range
+    /// or of the current line. The `argument` names the case, after the
+    /// optional range, and is prompted for when missing.
+    fn convert_case(&mut self, argument: &str) {
+        let (range, names) = match self.line_range(argument) {
+            Ok(result) => result,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                return;
+            }
+        };
+        let name = if let Some(&name) = names.first() {
+            name.to_owned()
+        } else {
+            match self.prompt("Case (upper, lower, title, snake, camel): ", |_, _, _| {}) {
+                Ok(Some(name)) => name,
+                _ => return,
+            }
+        };
+        let Some(case) = Case::from_name(&name) else {
+            self.status_message = StatusMessage::from(format!("ERR: Unknown case `{name}`"));
+            return;
+        };
+        let selection = self.selection().filter(|_| range.is_none());
+        let (start, end) = selection.clone().unwrap_or_else(|| {
+            let (first, last) = range.unwrap_or_else(|| self.selected_rows());
+            let width = self.document.row(last).map_or(0, Row::len);
+            (Position { x: 0, y: first }, Position { x: width, y: last })
+        });
+        let converted_end = self.document.convert(&start, &end, |text| transform::convert_case(text, case));
+        if selection.is_some() {
+            self.selection_anchor = Some(start);
+            self.cursor_position = converted_end;
+        } else {
+            let width = self.document.row(self.cursor_position.y).map_or(0, Row::len);
+            self.cursor_position.x = cmp::min(self.cursor_position.x, width);
+        }
+    }
+
+    /// Splits a command argument into the line range it may start with, as
+    /// the indexes of its first and last rows, and the remaining words
+    fn line_range<'a>(&self, argument: &'a str) -> Result<(Option<(usize, usize)>, Vec<&'a str>), std::io::Error> {
+        let mut words: Vec<&str> = argument.split_whitespace().collect();
+        let Some(range) = words.first().and_then(|&first| parse_range(first)) else {
+            return Ok((None, words));
+        };
+        words.remove(0);
+        let (first, last) = range;
+        if first == 0 || first > last || last > self.document.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid line range {first}-{last}"),
+            ));
+        }
+        Ok((Some((first.saturating_sub(1), last.saturating_sub(1))), words))
+    }
+
+    /// Moves the cursor and the selection `count` rows up or down, to
+    /// follow the lines they were on
+    fn shift_rows(&mut self, count: usize, direction: SearchDirection) {
+        let positions = [Some(&mut self.cursor_position), self.selection_anchor.as_mut()];
+        for position in positions.into_iter().flatten() {
+            position.y = if direction == SearchDirection::Forward {
+                position.y.saturating_add(count)
+            } else {
+                position.y.saturating_sub(count)
+            };
+        }
+    }
+
+    /// Keeps the cursor and the selection on the same text after `changes`
+    /// graphemes were added to, or removed from, the start of each row
+    /// from `start` on
+    fn shift_selection(&mut self, start: usize, changes: &[usize], added: bool) {
+        let positions = [Some(&mut self.cursor_position), self.selection_anchor.as_mut()];
+        for position in positions.into_iter().flatten() {
+            let Some(&change) = position.y.checked_sub(start).and_then(|offset| changes.get(offset)) else {
+                continue;
+            };
+            position.x = if added {
+                position.x.saturating_add(change)
+            } else {
+                position.x.saturating_sub(change)
+            };
+        }
+    }
+
+    /// Moves the cursor to the other bracket of the pair at or around it.
+    /// From inside a pair, it first moves to the opening bracket.
+    fn match_bracket(&mut self) {
+        let Some((first, second)) = self.document.bracket_pair(&self.cursor_position) else {
+            self.status_message = StatusMessage::from("No matching bracket".to_owned());
+            return;
+        };
+        let Position { x, y } = self.cursor_position;
+        let on_first = y == first.y && (x == first.x || x == first.x.saturating_add(1));
+        self.cursor_position = if on_first { second } else { first };
+        self.scroll();
+    }
+
+    fn set_file_type(&mut self, name: &str) {
+        let name = if name.is_empty() {
+            let available = filetype::language_names().join(", ");
+            match self.prompt(&format!("Filetype ({available}, none): "), |_, _, _| {}) {
+                Ok(Some(name)) => name,
+                _ => return,
+            }
+        } else {
+            name.to_owned()
+        };
+        if let Some(file_type) = FileType::by_name(&name) {
+            self.document.set_file_type(file_type);
+            self.status_message =
+                StatusMessage::from(format!("Filetype set to {}", self.document.file_type()));
+            self.attach_language_server();
+        } else {
+            self.status_message = StatusMessage::from(format!("ERR: Unknown filetype `{name}`"));
+        }
+    }
+
+    fn switch_theme(&mut self, name: &str) {
+        let name = if name.is_empty() {
+            let available = theme::available().join(", ");
+            match self.prompt(&format!("Theme ({available}): "), |_, _, _| {}) {
+                Ok(Some(name)) => name,
+                _ => return,
+            }
+        } else {
+            name.to_owned()
+        };
+        match Theme::load(&name) {
+            Ok(theme) => {
+                self.theme = theme.with_color_depth(self.color_depth);
+                self.status_message =
+                    StatusMessage::from(format!("Switched to theme {}", self.theme.name()));
+            }
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
+        }
+    }
+
+    fn scroll(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let width = self.text_width();
+        let height = self
+            .terminal
+            .size()
+            .height
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        let mut offset = &mut self.offset;
+
+        if y < offset.y {
+            offset.y = y;
+        } else if y >= offset.y.saturating_add(height) {
+            offset.y = y.saturating_sub(height).saturating_add(1);
+        }
+
+        if x < offset.x {
+            offset.x = x;
+        } else if x >= offset.x.saturating_add(width) {
+            offset.x = x.saturating_sub(width).saturating_add(1);
+        }
+    }
+
+    fn move_cursor(&mut self, key: Key) {
+        let terminal_height = self
+            .terminal
+            .size()
+            .height
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        let Position { mut x, mut y } = self.cursor_position;
+        let height = self.document.len();
+        let mut width = if let Some(row) = self.document.row(y) {
+            row.len()
+        } else {
+            0
+        };
+        match key {
+            Key::Up => y = y.saturating_sub(1),
+            Key::Down => {
+                if y < height {
+                    y = y.saturating_add(1);
+                }
+            }
+            Key::Left => {
+                if x > 0 {
+                    x = x.saturating_sub(1);
+                } else if y > 0 {
+                    y = y.saturating_sub(1);
+                    if let Some(row) = self.document.row(y) {
+                        x = row.len();
+                    } else {
+                        x = 0;
+                    }
+                }
+            }
+            Key::Right => {
+                if x < width {
+                    x = x.saturating_add(1);
+                } else if y < height {
+                    y = y.saturating_add(1);
+                    x = 0;
+                }
+            }
+            Key::PageUp => {
+                y = if y > terminal_height {
+                    y.saturating_sub(terminal_height)
+                } else {
+                    0
+                }
+            }
+            Key::PageDown => {
+                y = if y.saturating_add(terminal_height) < height {
+                    y.saturating_add(terminal_height)
+                } else {
+                    height
+                }
+            }
+            Key::Home => x = 0,
+            Key::End => x = width,
+            _ => (),
+        }
+        width = if let Some(row) = self.document.row(y) {
+            row.len()
+        } else {
+            0
+        };
+        if x > width {
+            x = width;
+        }
+        self.cursor_position = Position { x, y }
+    }
+
+    fn draw_welcome_message(&self) {
+        let mut welcome_message =
+            format!("Welcome to Hammare Editor \u{1f528} -- version {VERSION}");
+        let width: usize = self
+            .terminal
+            .size()
+            .width
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        let len = welcome_message.len();
+
+        let padding = width.saturating_sub(len) / 2;
+
+        let spaces = "  ".repeat(padding.saturating_sub(1));
+        welcome_message = format!("~{spaces}{welcome_message}");
+        welcome_message.truncate(width);
+        println!("{}\r", welcome_message);
+    }
+
+    /// Draw a single row based on the cursor position, with the current
+    /// line style if the cursor is on it, its number if shown, and the
+    /// diagnostics of the language server underlined and marked in the
+    /// gutter
+    pub fn draw_row(&self, row: &Row, index: usize) {
+        let width = self.text_width();
+        // The markers are drawn over the style of the gutter
+        let gutter = self.theme.ui(Element::LineNumbers);
+        let number_width = self.line_number_width();
+        if number_width > 0 {
+            Terminal::set_style(&gutter);
+            print!("{:>width$} ", index.saturating_add(1), width = number_width.saturating_sub(1));
+            Terminal::reset_style();
+        }
+        if self.gutter_width() > number_width {
+            if let Some(change) = self.row_change(index) {
+                let (marker, element) = match change {
+                    Change::Added => ('+', Element::GitAdded),
+                    Change::Modified => ('~', Element::GitModified),
+                    Change::Deleted => ('_', Element::GitDeleted),
+                };
+                Terminal::set_style(&gutter.layer(self.theme.ui(element)));
+                print!("{marker}");
+            } else {
+                Terminal::set_style(&gutter);
+                print!(" ");
+            }
+            if let Some(diagnostic) = self.row_diagnostic(index) {
+                Terminal::set_style(&gutter.layer(self.diagnostic_marker_style(diagnostic.severity)));
+                print!("{:<width$}", diagnostic.severity.marker(), width = GUTTER_WIDTH.saturating_sub(1));
+            } else {
+                Terminal::set_style(&gutter);
+                print!("{:width$}", "", width = GUTTER_WIDTH.saturating_sub(1));
+            }
+            Terminal::reset_style();
+        }
+        let start = self.offset.x;
+        let end = self.offset.x.saturating_add(width);
+        let base = if index == self.cursor_position.y {
+            self.theme.ui(Element::CurrentLine)
+        } else {
+            Style::default()
+        };
+        let bracket_style = self.theme.ui(Element::MatchingBracket);
+        let mut overlays: Vec<Span> = self
+            .bracket_pair
+            .iter()
+            .flat_map(|&(ref first, ref second)| [first, second])
+            .filter(|bracket| bracket.y == index)
+            .map(|bracket| Span {
+                start: bracket.x,
+                end: bracket.x.saturating_add(1),
+                style: bracket_style,
+            })
+            .collect();
+        let mut diagnostics: Vec<&lsp::Diagnostic> = self
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| (diagnostic.start.line..=diagnostic.end.line).contains(&index))
+            .collect();
+        // The most severe diagnostics are drawn last, over the others
+        diagnostics.sort_by_key(|diagnostic| cmp::Reverse(diagnostic.severity));
+        for (position, diagnostic) in diagnostics.into_iter().enumerate() {
+            let start = if index == diagnostic.start.line {
+                self.document.position_from_lsp(diagnostic.start).x
+            } else {
+                0
+            };
+            let end = if index == diagnostic.end.line {
+                self.document.position_from_lsp(diagnostic.end).x
+            } else {
+                usize::MAX
+            };
+            overlays.insert(
+                position,
+                Span {
+                    start,
+                    end: cmp::max(end, start.saturating_add(1)),
+                    style: self.diagnostic_style(diagnostic.severity),
+                },
+            );
+        }
+        if let Some((selection_start, selection_end)) = self.selection() {
+            if (selection_start.y..=selection_end.y).contains(&index) {
+                overlays.insert(
+                    0,
+                    Span {
+                        start: if index == selection_start.y { selection_start.x } else { 0 },
+                        end: if index == selection_end.y { selection_end.x } else { usize::MAX },
+                        style: self.theme.ui(Element::Selection),
+                    },
+                );
+            }
+        }
+        let row = row.render(start, end, &self.theme, base, &overlays);
+        println!("{}\r", row);
+    }
+
+    /// Draws the completion candidates in a popup under the word being
+    /// completed, or above it when there is no room below
+    fn draw_completion(&self) {
+        let Some(ref completion) = self.completion else {
+            return;
+        };
+        let height: usize = self
+            .terminal
+            .size()
+            .height
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        let width: usize = self
+            .terminal
+            .size()
+            .width
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        let candidates = completion.candidates();
+        let count = cmp::min(candidates.len(), MAX_COMPLETIONS);
+        let first = completion
+            .selected()
+            .map_or(0, |selected| selected.saturating_add(1).saturating_sub(count));
+        let row = self.cursor_position.y.saturating_sub(self.offset.y);
+        let top = if row.saturating_add(1).saturating_add(count) <= height {
+            row.saturating_add(1)
+        } else {
+            row.saturating_sub(count)
+        };
+        let left = completion
+            .start()
+            .x
+            .saturating_sub(self.offset.x)
+            .saturating_add(self.gutter_width());
+        let popup_width = candidates
+            .iter()
+            .map(|candidate| candidate.graphemes(true).count())
+            .max()
+            .unwrap_or(0)
+            .saturating_add(2);
+        let popup_width = cmp::min(popup_width, width.saturating_sub(left));
+        let popup_style = self.theme.ui(Element::Popup);
+        for (line, (index, candidate)) in candidates.iter().enumerate().skip(first).take(count).enumerate() {
+            let style = if completion.selected() == Some(index) {
+                popup_style.layer(Style {
+                    attributes: Attributes::REVERSE,
+                    ..Style::default()
+                })
+            } else {
+                popup_style
+            };
+            let text: String = format!(" {candidate:<popup_width$}").graphemes(true).take(popup_width).collect();
+            Terminal::cursor_position(&Position { x: left, y: top.saturating_add(line) });
+            Terminal::set_style(&style);
+            print!("{text}");
+        }
+        Terminal::reset_style();
+    }
+
+    /// Lists every key binding instead of the document rows
+    fn draw_help(&self) {
+        let mut lines = vec![
+            "Key bindings (press any key to close)".to_owned(),
+            String::new(),
+        ];
+        for (keys, action) in self.keymap.bindings() {
+            lines.push(format!(
+                "{:<16}{:<18}{}",
+                keymap::format_chord(keys),
+                action.name(),
+                action.description()
+            ));
+        }
+        for action in Action::all().filter(|&action| self.keymap.keys_for(action).is_none()) {
+            lines.push(format!(
+                "{:<16}{:<18}{}",
+                "unbound",
+                action.name(),
+                action.description()
+            ));
+        }
+        let width: usize = self
+            .terminal
+            .size()
+            .width
+            .try_into()
+            .expect("Failed converting terminal size to usize");
+        let mut lines = lines.into_iter();
+        for _ in 0..self.terminal.size().height {
+            Terminal::clear_current_line();
+            if let Some(mut line) = lines.next() {
+                line.truncate(width);
+                println!("{}\r", line);
+            } else {
+                println!("~\r");
+            }
+        }
+    }
+
+    fn draw_rows(&self) {
+        if self.show_help {
+            self.draw_help();
+            return;
+        }
+        let height = self.terminal.size().height;
+        for terminal_row in 0..height {
+            Terminal::clear_current_line();
+            let index = self.offset.y.saturating_add(
+                terminal_row
+                    .try_into()
+                    .expect("Failed trying to convert terminal_row into usize"),
+            );
+            if let Some(row) = self.document.row(index) {
+                self.draw_row(row, index);
+            } else if self.document.is_empty() && terminal_row == height / 3 {
+                self.draw_welcome_message();
+            } else {
+                println!("~\r");
+            }
+        }
+    }
+
+    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &String),
+    {
+        let mut result = String::new();
+        loop {
+            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
+            self.refresh_screen()?;
+
+            let key = self.terminal.read_key()?;
+
+            match key {
+                Key::Backspace => result.truncate(result.len().saturating_sub(1)),
+                Key::Char('\n') => break,
+                Key::Char(c) => {
+                    if !c.is_control() {
+                        result.push(c);
+                    }
+                }
                 Key::Esc => {
                     result.truncate(0);
                     break;
@@ -469,6 +2533,45 @@ impl Editor {
         }
         Ok(Some(result))
     }
+
+    /// Asks a question answered by pressing one of the `answers` keys, or
+    /// `Esc` to answer none
+    fn ask(&mut self, question: &str, answers: &[char]) -> Result<Option<char>, std::io::Error> {
+        let answer = loop {
+            self.status_message = StatusMessage::from(question.to_owned());
+            self.refresh_screen()?;
+            match self.terminal.read_key()? {
+                Key::Char(c) if answers.contains(&c.to_ascii_lowercase()) => break Some(c.to_ascii_lowercase()),
+                Key::Esc => break None,
+                _ => (),
+            }
+        };
+        self.status_message = StatusMessage::from(String::new());
+        Ok(answer)
+    }
+}
+
+/// Parses a line range written `first-last` or `first,last`, counting lines
+/// from 1
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (first, last) = text.split_once('-').or_else(|| text.split_once(','))?;
+    Some((first.parse().ok()?, last.parse().ok()?))
+}
+
+/// Checks if two paths name the same file, comparing them as given when
+/// one of them does not exist
+fn same_file(first: &str, second: &str) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => first == second,
+    }
+}
+
+/// Formats the chord bound to `action`, or `unbound` if there is none
+fn bound_keys(keymap: &Keymap, action: Action) -> String {
+    keymap
+        .keys_for(action)
+        .map_or_else(|| "unbound".to_owned(), keymap::format_chord)
 }
 
 fn die(e: &std::io::Error) {
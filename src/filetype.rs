@@ -1,16 +1,67 @@
+use crate::config;
+use crate::glob;
+use crate::Config;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Language definitions shipped with the editor
+const BUILTIN_LANGUAGES: [&str; 13] = [
+    include_str!("../languages/c.ini"),
+    include_str!("../languages/diff.ini"),
+    include_str!("../languages/dockerfile.ini"),
+    include_str!("../languages/ini.ini"),
+    include_str!("../languages/json.ini"),
+    include_str!("../languages/makefile.ini"),
+    include_str!("../languages/markdown.ini"),
+    include_str!("../languages/python.ini"),
+    include_str!("../languages/rust.ini"),
+    include_str!("../languages/shell.ini"),
+    include_str!("../languages/sql.ini"),
+    include_str!("../languages/toml.ini"),
+    include_str!("../languages/yaml.ini"),
+];
+
+#[derive(Clone)]
 pub struct FileType {
     name: String,
+    extensions: Vec<String>,
+    file_names: Vec<String>,
+    globs: Vec<String>,
+    shebangs: Vec<String>,
+    /// The identifier of the language for language servers, when it is not
+    /// the lowercase name
+    language_id: Option<String>,
+    /// The command starting the language server, with its arguments
+    language_server: Option<String>,
+    /// The command formatting the text given on its standard input
+    formatter: Option<String>,
     hl_opts: HighlightingOptions,
 }
 
-#[derive(Default)]
-#[allow(clippy::struct_excessive_bools)]
+#[derive(Default, Clone)]
 pub struct HighlightingOptions {
     numbers: bool,
-    strings: bool,
     characters: bool,
-    comments: bool,
-    multiline_comments: bool,
+    line_comments: Vec<String>,
+    block_comment: Option<(String, String)>,
+    string_delimiters: Vec<char>,
+    multiline_strings: bool,
+    string_escape: Option<char>,
+    string_prefixes: Vec<String>,
+    raw_strings: bool,
+    nested_comments: bool,
+    doc_comments: Vec<String>,
+    block_doc_comments: Vec<String>,
+    attributes: Vec<String>,
+    macros: bool,
+    lifetimes: bool,
+    diff_lines: bool,
+    number_prefixes: Vec<String>,
+    number_separator: Option<char>,
+    number_suffixes: Vec<String>,
+    number_exponents: bool,
     primary_keywords: Vec<String>,
     secondary_keywords: Vec<String>
 }
@@ -19,6 +70,13 @@ impl Default for FileType {
     fn default() -> Self {
         Self {
             name: String::from("No filetype"),
+            extensions: Vec::new(),
+            file_names: Vec::new(),
+            globs: Vec::new(),
+            shebangs: Vec::new(),
+            language_id: None,
+            language_server: None,
+            formatter: None,
             hl_opts: HighlightingOptions::default(),
         }
     }
@@ -35,97 +93,301 @@ impl FileType {
     pub fn highlighting_options(&self) -> &HighlightingOptions {
         &self.hl_opts
     }
+    /// Returns the identifier of the language for language servers, which
+    /// defaults to the lowercase name
+    #[must_use]
+    pub fn language_id(&self) -> String {
+        self.language_id.clone().unwrap_or_else(|| self.name.to_lowercase())
+    }
+    /// Returns the command starting the language server, if one is
+    /// configured
+    #[must_use]
+    pub fn language_server(&self) -> Option<&str> {
+        self.language_server.as_deref()
+    }
+    /// Returns the command formatting the file, if one is configured
+    #[must_use]
+    pub fn formatter(&self) -> Option<&str> {
+        self.formatter.as_deref()
+    }
     /// Gets the documents extenstion type
     #[must_use]
     pub fn from(file_name: &str) -> Self {
-        if file_name
-            .rsplit('.')
-            .next()
-            .map(|ext| ext.eq_ignore_ascii_case("rs"))
-            == Some(true)
-        {
-            return Self {
-                name: String::from("Rust"),
+        Self::detect(file_name, &[])
+    }
+
+    /// Detects the type of a file from, in order of precedence: a modeline
+    /// in `lines`, its exact file name, a glob pattern matching the file
+    /// name, its extension, and the shebang on the first of `lines`.
+    /// `lines` are the first and last rows of the file.
+    #[must_use]
+    pub fn detect(file_name: &str, lines: &[&str]) -> Self {
+        let languages = known_languages();
+        // Later definitions take precedence, so that user languages override
+        // the built-in ones.
+        let find = |predicate: &dyn Fn(&Self) -> bool| {
+            languages.iter().rev().find(|&file_type| predicate(file_type)).cloned()
+        };
+        let base_name = file_name.rsplit('/').next().unwrap_or_default();
+        let extension = base_name
+            .rsplit_once('.')
+            .map_or("", |(_, extension)| extension);
+
+        let by_modeline = || {
+            lines
+                .iter()
+                .find_map(|line| modeline_file_type(line))
+                .and_then(|name| find(&|file_type| file_type.is_named(name)))
+        };
+        let by_file_name = || find(&|file_type| file_type.file_names.iter().any(|name| name == base_name));
+        let by_glob = || {
+            find(&|file_type| {
+                file_type
+                    .globs
+                    .iter()
+                    .any(|pattern| glob::matches(pattern, base_name))
+            })
+        };
+        let by_extension = || {
+            find(&|file_type| {
+                !extension.is_empty()
+                    && file_type
+                        .extensions
+                        .iter()
+                        .any(|ext| ext.eq_ignore_ascii_case(extension))
+            })
+        };
+        let by_shebang = || {
+            lines
+                .first()
+                .and_then(|line| shebang_interpreter(line))
+                .and_then(|interpreter| {
+                    find(&|file_type| file_type.shebangs.iter().any(|name| name == interpreter))
+                })
+        };
+        by_modeline()
+            .or_else(by_file_name)
+            .or_else(by_glob)
+            .or_else(by_extension)
+            .or_else(by_shebang)
+            .unwrap_or_default()
+    }
+
+    /// Returns the language called `name`, compared without case, or whose
+    /// extensions include `name`. `none` resets to no filetype.
+    #[must_use]
+    pub fn by_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("none") {
+            return Some(Self::default());
+        }
+        known_languages()
+            .iter()
+            .rev()
+            .find(|file_type| file_type.is_named(name))
+            .cloned()
+    }
+
+    fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.extensions.iter().any(|ext| ext.eq_ignore_ascii_case(name))
+    }
+
+    /// Parses the `[language]` sections of a language definition file.
+    ///
+    /// # Errors
+    /// It will return `Err` if the file cannot be parsed, a language has no
+    /// name or a value is invalid
+    pub fn parse(contents: &str) -> Result<Vec<Self>, Error> {
+        let definitions = Config::parse(contents)?;
+        let mut result = Vec::new();
+        for section in definitions.sections("language") {
+            let name = section.get("name").ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "Language definition without a name")
+            })?;
+            let words = |key: &str| -> Vec<String> {
+                section
+                    .get(key)
+                    .map(|value| value.split_whitespace().map(str::to_owned).collect())
+                    .unwrap_or_default()
+            };
+            let flag = |key: &str| -> Result<bool, Error> {
+                section.get(key).map_or(Ok(false), |value| {
+                    value.parse().map_err(|_| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("{name}: `{key}` must be `true` or `false`"),
+                        )
+                    })
+                })
+            };
+            let block_comment = match words("block_comment").as_slice() {
+                [] => None,
+                [start, end] => Some((start.clone(), end.clone())),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{name}: `block_comment` needs a start and an end token"),
+                    ))
+                }
+            };
+            result.push(Self {
+                name: name.to_owned(),
+                extensions: words("extensions"),
+                file_names: words("file_names"),
+                globs: words("globs"),
+                shebangs: words("shebangs"),
+                language_id: section.get("language_id").map(str::to_owned),
+                language_server: section.get("language_server").map(str::to_owned),
+                formatter: section.get("formatter").map(str::to_owned),
                 hl_opts: HighlightingOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    multiline_comments: true,
-                    primary_keywords: vec![
-                        "as".to_owned(),            
-                        "break".to_owned(),            
-                        "const".to_owned(),            
-                        "continue".to_owned(),            
-                        "crate".to_owned(),            
-                        "else".to_owned(),            
-                        "enum".to_owned(),            
-                        "extern".to_owned(),            
-                        "false".to_owned(),            
-                        "fn".to_owned(),            
-                        "for".to_owned(),            
-                        "if".to_owned(),            
-                        "impl".to_owned(),            
-                        "in".to_owned(),            
-                        "let".to_owned(),            
-                        "loop".to_owned(),            
-                        "match".to_owned(),            
-                        "mod".to_owned(),            
-                        "move".to_owned(),            
-                        "mut".to_owned(),            
-                        "pub".to_owned(),            
-                        "ref".to_owned(),            
-                        "return".to_owned(),            
-                        "self".to_owned(),            
-                        "Self".to_owned(),            
-                        "static".to_owned(),            
-                        "struct".to_owned(),            
-                        "super".to_owned(),            
-                        "trait".to_owned(),            
-                        "true".to_owned(),            
-                        "type".to_owned(),            
-                        "unsafe".to_owned(),            
-                        "use".to_owned(),            
-                        "where".to_owned(),            
-                        "while".to_owned(),            
-                        "dyn".to_owned(),            
-                        "abstract".to_owned(),            
-                        "become".to_owned(),            
-                        "box".to_owned(),            
-                        "do".to_owned(),            
-                        "final".to_owned(),            
-                        "macro".to_owned(),            
-                        "override".to_owned(),            
-                        "priv".to_owned(),            
-                        "typeof".to_owned(),            
-                        "unsized".to_owned(),            
-                        "virtual".to_owned(),            
-                        "yield".to_owned(),            
-                        "async".to_owned(),            
-                        "await".to_owned(),            
-                        "try".to_owned(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_owned(),            
-                        "char".to_owned(),            
-                        "i8".to_owned(),            
-                        "i16".to_owned(),            
-                        "i32".to_owned(),            
-                        "i64".to_owned(),            
-                        "isize".to_owned(),            
-                        "u8".to_owned(),            
-                        "u16".to_owned(),            
-                        "u32".to_owned(),            
-                        "u64".to_owned(),            
-                        "usize".to_owned(),            
-                        "f32".to_owned(),            
-                        "f64".to_owned(),  
-                    ]
+                    numbers: flag("numbers")?,
+                    characters: flag("characters")?,
+                    line_comments: words("line_comment"),
+                    block_comment,
+                    string_delimiters: words("string_delimiters")
+                        .iter()
+                        .flat_map(|delimiter| delimiter.chars())
+                        .collect(),
+                    multiline_strings: flag("multiline_strings")?,
+                    string_escape: section
+                        .get("string_escape")
+                        .and_then(|escape| escape.chars().next()),
+                    string_prefixes: words("string_prefixes"),
+                    raw_strings: flag("raw_strings")?,
+                    nested_comments: flag("nested_comments")?,
+                    doc_comments: words("doc_comments"),
+                    block_doc_comments: words("block_doc_comments"),
+                    attributes: words("attributes"),
+                    macros: flag("macros")?,
+                    lifetimes: flag("lifetimes")?,
+                    diff_lines: flag("diff_lines")?,
+                    number_prefixes: words("number_prefixes"),
+                    number_separator: section
+                        .get("number_separator")
+                        .and_then(|separator| separator.chars().next()),
+                    number_suffixes: words("number_suffixes"),
+                    number_exponents: flag("number_exponents")?,
+                    primary_keywords: words("primary_keywords"),
+                    secondary_keywords: words("secondary_keywords"),
                 },
-            };
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Returns the built-in language definitions followed by the ones found in
+/// the `languages` directory of the user configuration, so that a user
+/// definition takes precedence over a built-in one for the same extension.
+///
+/// # Errors
+/// It will return `Err` if a user definition cannot be read or parsed
+pub fn languages() -> Result<Vec<FileType>, Error> {
+    let mut result = builtin_languages();
+    if let Some(dir) = config::config_dir().map(|dir| dir.join("languages")) {
+        if dir.is_dir() {
+            let mut paths: Vec<_> = fs::read_dir(dir)?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "ini"))
+                .collect();
+            paths.sort();
+            for path in paths {
+                result.extend(parse_file(&path)?);
+            }
         }
-        Self::default()
     }
+    Ok(result)
+}
+
+/// Returns the names of the known languages
+#[must_use]
+pub fn language_names() -> Vec<String> {
+    let mut names: Vec<String> = known_languages()
+        .iter()
+        .map(|file_type| file_type.name.clone())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Returns the languages read by `languages` the first time it is needed,
+/// or only the built-in ones if the user definitions are invalid
+fn known_languages() -> &'static [FileType] {
+    static LANGUAGES: OnceLock<Vec<FileType>> = OnceLock::new();
+    LANGUAGES.get_or_init(|| languages().unwrap_or_else(|_| builtin_languages()))
+}
+
+fn builtin_languages() -> Vec<FileType> {
+    BUILTIN_LANGUAGES
+        .iter()
+        .flat_map(|contents| FileType::parse(contents).expect("Built-in languages are valid"))
+        .collect()
+}
+
+fn parse_file(path: &Path) -> Result<Vec<FileType>, Error> {
+    FileType::parse(&fs::read_to_string(path)?).map_err(|error| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), error),
+        )
+    })
+}
+
+/// Returns the name of the interpreter of a `#!` line, without its
+/// directory, `env` indirection or version number: `#!/usr/bin/env python3`
+/// gives `python`.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    Some(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+        .filter(|name| !name.is_empty())
+}
+
+/// Returns the filetype set by a vim (`vim: ft=python`, `vim: set
+/// filetype=sh:`), emacs (`-*- mode: python -*-`) or hammare
+/// (`hammare: ft=python`) modeline
+fn modeline_file_type(line: &str) -> Option<&str> {
+    if let Some((_, rest)) = line.split_once("-*-") {
+        if let Some((inner, _)) = rest.split_once("-*-") {
+            if !inner.contains(':') {
+                return Some(inner.trim()).filter(|mode| !mode.is_empty());
+            }
+            return inner.split(';').find_map(|variable| {
+                let (key, value) = variable.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case("mode")
+                    .then(|| value.trim())
+            });
+        }
+    }
+    for marker in ["vim:", "vi:", "ex:", "hammare:"] {
+        if let Some(position) = line.find(marker) {
+            let preceded_by_space = line
+                .get(..position)
+                .map_or(false, |before| before.is_empty() || before.ends_with(char::is_whitespace));
+            if !preceded_by_space {
+                continue;
+            }
+            let options = line.get(position.saturating_add(marker.len())..).unwrap_or_default();
+            let file_type = options
+                .split(|c: char| c.is_whitespace() || c == ':')
+                .find_map(|option| {
+                    option
+                        .strip_prefix("ft=")
+                        .or_else(|| option.strip_prefix("filetype="))
+                        .or_else(|| option.strip_prefix("syntax="))
+                });
+            if file_type.is_some() {
+                return file_type;
+            }
+        }
+    }
+    None
 }
 
 impl HighlightingOptions {
@@ -138,7 +400,7 @@ impl HighlightingOptions {
     /// Return a boolean indicating if we should highlight strings or not
     #[must_use]
     pub fn strings(&self) -> bool {
-        self.strings
+        !self.string_delimiters.is_empty()
     }
 
     /// Return a boolean indicating if we should highlight characters or not
@@ -150,17 +412,17 @@ impl HighlightingOptions {
     /// Return a boolean indicating if we should highlight comments or not
     #[must_use]
     pub fn comments(&self) -> bool {
-        self.comments
+        !self.line_comments.is_empty()
     }
 
-    /// Return a boolean indicating if we should highlight words that are in 
+    /// Return a boolean indicating if we should highlight words that are in
     /// the `primary_keywords` vector or not
     #[must_use]
     pub fn primary_keywords(&self) -> &Vec<String> {
         &self.primary_keywords
     }
 
-    /// Return a boolean indicating if we should highlight words that are in 
+    /// Return a boolean indicating if we should highlight words that are in
     /// the `secondary_keywords` vector or not
     #[must_use]
     pub fn secondary_keywords(&self) -> &Vec<String> {
@@ -170,7 +432,179 @@ impl HighlightingOptions {
     /// Return a boolean indicating if we should highlight multiline comments
     #[must_use]
     pub fn multiline_comments(&self) -> bool {
-        self.multiline_comments
+        self.block_comment.is_some()
+    }
+
+    /// Returns the tokens starting a comment that runs until the end of the
+    /// line, the preferred one first
+    #[must_use]
+    pub fn line_comments(&self) -> &[String] {
+        &self.line_comments
+    }
+
+    /// Returns the tokens starting and ending a block comment
+    #[must_use]
+    pub fn block_comment(&self) -> Option<(&str, &str)> {
+        self.block_comment
+            .as_ref()
+            .map(|&(ref start, ref end)| (start.as_str(), end.as_str()))
+    }
+
+    /// Returns the characters that open and close a string
+    #[must_use]
+    pub fn string_delimiters(&self) -> &[char] {
+        &self.string_delimiters
     }
 
+    /// Return a boolean indicating if strings can span several rows
+    #[must_use]
+    pub fn multiline_strings(&self) -> bool {
+        self.multiline_strings
+    }
+
+    /// Returns the character starting an escape sequence in strings
+    #[must_use]
+    pub fn string_escape(&self) -> Option<char> {
+        self.string_escape
+    }
+
+    /// Returns the prefixes a string delimiter may follow, such as `b`
+    #[must_use]
+    pub fn string_prefixes(&self) -> &[String] {
+        &self.string_prefixes
+    }
+
+    /// Return a boolean indicating if `r"..."` and `r#"..."#` raw strings
+    /// are highlighted
+    #[must_use]
+    pub fn raw_strings(&self) -> bool {
+        self.raw_strings
+    }
+
+    /// Return a boolean indicating if block comments can be nested
+    #[must_use]
+    pub fn nested_comments(&self) -> bool {
+        self.nested_comments
+    }
+
+    /// Returns the tokens starting a documentation line comment
+    #[must_use]
+    pub fn doc_comments(&self) -> &[String] {
+        &self.doc_comments
+    }
+
+    /// Returns the tokens starting a documentation block comment
+    #[must_use]
+    pub fn block_doc_comments(&self) -> &[String] {
+        &self.block_doc_comments
+    }
+
+    /// Returns the tokens opening an attribute, such as `#[`
+    #[must_use]
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    /// Return a boolean indicating if `name!` macro invocations are
+    /// highlighted
+    #[must_use]
+    pub fn macros(&self) -> bool {
+        self.macros
+    }
+
+    /// Return a boolean indicating if `'a` lifetimes are highlighted
+    #[must_use]
+    pub fn lifetimes(&self) -> bool {
+        self.lifetimes
+    }
+
+    /// Return a boolean indicating if whole lines are highlighted as the
+    /// lines of a unified diff, from their first characters
+    #[must_use]
+    pub fn diff_lines(&self) -> bool {
+        self.diff_lines
+    }
+
+    /// Return a boolean indicating if numbers can have an exponent, such
+    /// as `1e-9`
+    #[must_use]
+    pub fn number_exponents(&self) -> bool {
+        self.number_exponents
+    }
+
+    /// Returns the prefixes of non-decimal number literals, such as `0x`
+    #[must_use]
+    pub fn number_prefixes(&self) -> &[String] {
+        &self.number_prefixes
+    }
+
+    /// Returns the character allowed between digits, such as `_`
+    #[must_use]
+    pub fn number_separator(&self) -> Option<char> {
+        self.number_separator
+    }
+
+    /// Returns the type suffixes number literals may end with
+    #[must_use]
+    pub fn number_suffixes(&self) -> &[String] {
+        &self.number_suffixes
+    }
+
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse_language() {
+        let languages = FileType::parse(
+            "[language]\nname = Lua\nextensions = lua\nline_comment = --\nblock_comment = --[[ ]]\nstring_delimiters = \" '\nnumbers = true\nprimary_keywords = local function end\n",
+        )
+        .expect("valid definition");
+        let lua = languages.first().expect("one language");
+        assert_eq!(lua.name(), "Lua");
+        let opts = lua.highlighting_options();
+        assert!(opts.numbers());
+        assert!(!opts.characters());
+        assert_eq!(opts.line_comments(), &["--".to_owned()]);
+        assert_eq!(opts.block_comment(), Some(("--[[", "]]")));
+        assert_eq!(opts.string_delimiters(), &['"', '\'']);
+        assert_eq!(opts.primary_keywords().len(), 3);
+        assert!(FileType::parse("[language]\nextensions = lua\n").is_err());
+        assert!(FileType::parse("[language]\nname = X\nnumbers = yes\n").is_err());
+    }
+
+    #[test]
+    fn test_builtin_languages() {
+        assert_eq!(FileType::from("src/main.rs").name(), "Rust");
+        assert_eq!(FileType::from("src/main.rs").language_server(), Some("rust-analyzer"));
+        assert_eq!(FileType::from("src/main.rs").formatter(), Some("rustfmt --emit stdout --edition 2021"));
+        assert_eq!(FileType::from("Cargo.toml").name(), "TOML");
+        assert_eq!(FileType::from("src/main.rs").language_id(), "rust");
+        assert_eq!(FileType::from("install.sh").language_id(), "shellscript");
+        assert_eq!(FileType::from("script.PY").name(), "Python");
+        assert_eq!(FileType::from("notes").name(), "No filetype");
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(FileType::detect("src/Makefile", &[]).name(), "Makefile");
+        assert_eq!(FileType::detect("/home/me/.bashrc", &[]).name(), "Shell");
+        assert_eq!(FileType::detect("Dockerfile.dev", &[]).name(), "Dockerfile");
+        assert_eq!(
+            FileType::detect("bin/tool", &["#!/usr/bin/env python3", "print()"]).name(),
+            "Python"
+        );
+        assert_eq!(FileType::detect("run", &["#!/bin/bash -e"]).name(), "Shell");
+        assert_eq!(
+            FileType::detect("notes.txt", &["# vim: set ft=yaml ts=2:"]).name(),
+            "YAML"
+        );
+        assert_eq!(
+            FileType::detect("main.c", &["/* -*- mode: rust -*- */"]).name(),
+            "Rust"
+        );
+        assert_eq!(FileType::by_name("py").map(|file_type| file_type.name()), Some("Python".to_owned()));
+    }
 }
@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Returns the text of the file at `path` in the `HEAD` commit of the git
+/// repository containing it. Returns `None` outside of a repository, for a
+/// file that was never committed, or when `git` is not installed.
+#[must_use]
+pub fn head_text(path: &Path) -> Option<String> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let name = path.file_name()?.to_str()?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("HEAD:./{name}"))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_head_text() {
+        let root = std::env::temp_dir().join(format!("hammare-git-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).expect("created");
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").expect("written");
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&root)
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .map_or(false, |output| output.status.success())
+        };
+        assert!(git(&["init", "-q"]) && git(&["add", "src/main.rs"]) && git(&["commit", "-q", "-m", "Initial"]));
+        fs::write(root.join("src/main.rs"), "fn main() {\n}\n").expect("written");
+        fs::write(root.join("new.rs"), "\n").expect("written");
+        let committed = head_text(&root.join("src/main.rs"));
+        let untracked = head_text(&root.join("new.rs"));
+        fs::remove_dir_all(&root).expect("removed");
+        assert_eq!(committed.as_deref(), Some("fn main() {}\n"));
+        assert_eq!(untracked, None);
+    }
+}
@@ -0,0 +1,50 @@
+/// Checks if `text` matches the shell-style `pattern`, where `*` matches any
+/// sequence of characters, `?` any single character, and every other
+/// character itself.
+#[must_use]
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen, and of the text it was matched against,
+    // to backtrack to when the rest of the pattern fails to match.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match (pattern.get(p), text.get(t)) {
+            (Some('*'), _) => {
+                backtrack = Some((p, t));
+                p = p.saturating_add(1);
+            }
+            (Some(&expected), Some(&actual)) if expected == '?' || expected == actual => {
+                p = p.saturating_add(1);
+                t = t.saturating_add(1);
+            }
+            _ => {
+                if let Some((star, matched)) = backtrack {
+                    p = star.saturating_add(1);
+                    t = matched.saturating_add(1);
+                    backtrack = Some((star, t));
+                } else {
+                    return false;
+                }
+            }
+        }
+    }
+    pattern.get(p..).map_or(true, |rest| rest.iter().all(|&c| c == '*'))
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("Dockerfile.*", "Dockerfile.dev"));
+        assert!(matches("?akefile", "Makefile"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+    }
+}
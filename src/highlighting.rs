@@ -1,4 +1,19 @@
-use termion::color;
+/// The lexer state carried from the end of a row to the start of the next
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum State {
+    #[default]
+    Normal,
+    /// Inside `depth` nested block comments, which are doc comments if
+    /// `doc` is set
+    BlockComment { depth: u8, doc: bool },
+    /// Inside a string opened with the given delimiter
+    String(char),
+    /// Inside a raw string, closed by `"` and the given number of `#`
+    RawString(u8),
+    /// Inside a hunk of a unified diff, with the numbers of lines of the
+    /// old and the new text left in it
+    DiffHunk { old: usize, new: usize },
+}
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Type {
@@ -10,19 +25,73 @@ pub enum Type {
     Comment,
     MultilineComment,
     PrimaryKeywords,
-    SecondaryKeywords
+    SecondaryKeywords,
+    Lifetime,
+    Attribute,
+    Macro,
+    DocComment,
+    Escape,
+    /// Lines of a diff
+    DiffAdded,
+    DiffRemoved,
+    DiffHeader,
 }
 impl Type {
-    pub fn to_color(self) -> impl color::Color {
+    pub const ALL: [Self; 17] = [
+        Type::None,
+        Type::Number,
+        Type::Match,
+        Type::String,
+        Type::Character,
+        Type::Comment,
+        Type::MultilineComment,
+        Type::PrimaryKeywords,
+        Type::SecondaryKeywords,
+        Type::Lifetime,
+        Type::Attribute,
+        Type::Macro,
+        Type::DocComment,
+        Type::Escape,
+        Type::DiffAdded,
+        Type::DiffRemoved,
+        Type::DiffHeader,
+    ];
+
+    /// Returns the name used for the type in theme files
+    pub fn name(self) -> &'static str {
         match self {
-            Type::Number => color::Rgb(189, 147, 249),
-            Type::Match => color::Rgb(38, 139, 210),
-            Type::String => color::Rgb(241, 250, 140),
-            Type::Character => color::Rgb(108, 113, 196),
-            Type::Comment | Type::MultilineComment => color::Rgb(98, 114, 164),
-            Type::PrimaryKeywords => color::Rgb(255, 121, 198),
-            Type::SecondaryKeywords => color::Rgb(139, 233, 253),
-            Type::None => color::Rgb(255, 255, 255),
+            Type::None => "none",
+            Type::Number => "number",
+            Type::Match => "match",
+            Type::String => "string",
+            Type::Character => "character",
+            Type::Comment => "comment",
+            Type::MultilineComment => "multiline_comment",
+            Type::PrimaryKeywords => "primary_keywords",
+            Type::SecondaryKeywords => "secondary_keywords",
+            Type::Lifetime => "lifetime",
+            Type::Attribute => "attribute",
+            Type::Macro => "macro",
+            Type::DocComment => "doc_comment",
+            Type::Escape => "escape",
+            Type::DiffAdded => "diff_added",
+            Type::DiffRemoved => "diff_removed",
+            Type::DiffHeader => "diff_header",
         }
     }
+
+    /// Checks if the type marks text inside a string or a comment, where
+    /// brackets and the like do not count as code
+    #[must_use]
+    pub fn is_string_or_comment(self) -> bool {
+        matches!(
+            self,
+            Type::String
+                | Type::Character
+                | Type::Escape
+                | Type::Comment
+                | Type::MultilineComment
+                | Type::DocComment
+        )
+    }
 }
@@ -0,0 +1,111 @@
+use crate::glob;
+
+/// One pattern of a `.gitignore` file
+struct Rule {
+    /// The directory of the `.gitignore` file, relative to the project root
+    /// and ending with `/`, or empty at the root
+    base: String,
+    pattern: String,
+    /// `!pattern` includes again what an earlier pattern excluded
+    negated: bool,
+    /// `pattern/` only matches directories
+    directory_only: bool,
+    /// A pattern with a `/` other than at its end matches the path relative
+    /// to `base`, and any other pattern the name of the file
+    anchored: bool,
+}
+
+/// The patterns of the `.gitignore` files found in a project, telling
+/// which of its files are ignored
+#[derive(Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// Adds the patterns of a `.gitignore` file found in `base`, the
+    /// directory relative to the project root, empty for the root itself.
+    /// Patterns use the syntax of `glob::matches`; character classes are
+    /// not supported.
+    pub fn add(&mut self, base: &str, contents: &str) {
+        let base = if base.is_empty() || base.ends_with('/') {
+            base.to_owned()
+        } else {
+            format!("{base}/")
+        };
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (directory_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (anchored, pattern) = if let Some(pattern) = line.strip_prefix("**/") {
+                (false, pattern)
+            } else if let Some(pattern) = line.strip_prefix('/') {
+                (true, pattern)
+            } else {
+                (line.contains('/'), line)
+            };
+            self.rules.push(Rule {
+                base: base.clone(),
+                pattern: pattern.to_owned(),
+                negated,
+                directory_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Checks if `path`, relative to the project root and separated with
+    /// `/`, is ignored. The last matching pattern decides.
+    #[must_use]
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let mut ignored = false;
+        for rule in &self.rules {
+            let Some(relative) = path.strip_prefix(rule.base.as_str()) else {
+                continue;
+            };
+            if rule.directory_only && !is_dir {
+                continue;
+            }
+            let matched = if rule.anchored {
+                glob::matches(&rule.pattern, relative)
+            } else {
+                glob::matches(&rule.pattern, name)
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let mut ignore = Ignore::default();
+        ignore.add("", "# build output\n/target\n*.log\n!keep.log\nnode_modules/\ndocs/*.html\n");
+        ignore.add("src", "generated.rs\n");
+        assert!(ignore.is_ignored("target", true));
+        assert!(!ignore.is_ignored("src/target", true));
+        assert!(ignore.is_ignored("logs/run.log", false));
+        assert!(!ignore.is_ignored("logs/keep.log", false));
+        assert!(ignore.is_ignored("web/node_modules", true));
+        assert!(!ignore.is_ignored("node_modules", false));
+        assert!(ignore.is_ignored("docs/index.html", false));
+        assert!(ignore.is_ignored("src/generated.rs", false));
+        assert!(!ignore.is_ignored("generated.rs", false));
+    }
+}
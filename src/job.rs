@@ -0,0 +1,286 @@
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a command filtering text may run before it is killed
+const FILTER_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a filtering command is checked for its exit
+const FILTER_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A shell command running in the background, whose standard output and
+/// error are collected line by line as they are written
+pub struct Job {
+    child: Child,
+    output: Receiver<String>,
+    /// Whether both output streams were read to their end
+    output_done: bool,
+    status: Option<ExitStatus>,
+}
+
+impl Job {
+    /// Runs `command` with `sh -c` in the current directory, in a process
+    /// group of its own so that it can be stopped with the processes it
+    /// starts
+    ///
+    /// # Errors
+    /// It will return `Err` if the shell cannot be started
+    pub fn spawn(command: &str) -> Result<Self, Error> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (sender, output) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            read_lines(stdout, sender.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            read_lines(stderr, sender);
+        }
+        Ok(Self {
+            child,
+            output,
+            output_done: false,
+            status: None,
+        })
+    }
+
+    /// Returns the lines written since the last call, without waiting for
+    /// more. Lines of the output and of the errors keep their own order,
+    /// but may be interleaved differently than in a terminal.
+    pub fn poll(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        while !self.output_done {
+            match self.output.try_recv() {
+                Ok(line) => lines.push(line),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.output_done = true,
+            }
+        }
+        lines
+    }
+
+    /// Returns the exit status once the command exited and all of its
+    /// output was polled
+    pub fn status(&mut self) -> Option<ExitStatus> {
+        if self.output_done && self.status.is_none() {
+            self.status = self.child.try_wait().ok().flatten();
+        }
+        self.status
+    }
+
+    /// Stops the command and the processes it started
+    pub fn kill(&mut self) {
+        kill_group(&mut self.child);
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        if self.status.is_none() {
+            self.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Kills the process group led by `child`, or only `child` if the group
+/// cannot be signaled
+fn kill_group(child: &mut Child) {
+    let killed = libc::pid_t::try_from(child.id()).map_or(false, |pid| {
+        // SAFETY: `killpg` only sends a signal and takes no pointer; `pid`
+        // is the group of `child`, which was spawned as its own group leader
+        // and is not reaped before it is killed.
+        unsafe { libc::killpg(pid, libc::SIGKILL) == 0 }
+    });
+    if !killed {
+        let _ = child.kill();
+    }
+}
+
+/// Runs `command` with `sh -c`, writing `input` to its standard input, and
+/// returns its standard output. The command is killed if it runs for longer
+/// than `FILTER_TIMEOUT`.
+///
+/// # Errors
+/// It will return `Err` if the shell cannot be started, its input cannot be
+/// written, it times out, or with the first line of the standard error if
+/// the command fails
+pub fn filter(command: &str, input: &str) -> Result<String, Error> {
+    filter_within(command, input, FILTER_TIMEOUT)
+}
+
+fn filter_within(command: &str, input: &str, timeout: Duration) -> Result<String, Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .process_group(0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Written and read from other threads, as the command may fill its
+    // output before reading all of its input
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_owned();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(stdin.write_all(input.as_bytes()));
+        });
+        receiver
+    });
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let start = Instant::now();
+    let give_up = |shell: &mut Child| {
+        kill_group(shell);
+        let _ = shell.wait();
+        Error::new(
+            ErrorKind::TimedOut,
+            format!("`{command}` timed out after {timeout:?}"),
+        )
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            return Err(give_up(&mut child));
+        }
+        thread::sleep(FILTER_POLL_INTERVAL);
+    };
+    // The processes the command started in the background may still hold
+    // its input or output open
+    let output = receive_within(stdout, start, timeout)
+        .map_err(|_| give_up(&mut child))?
+        .unwrap_or_default();
+    let errors = receive_within(stderr, start, timeout)
+        .map_err(|_| give_up(&mut child))?
+        .unwrap_or_default();
+    if status.success() {
+        // A command may stop reading its input once it has what it needs
+        match receive_within(writer, start, timeout).map_err(|_| give_up(&mut child))? {
+            Some(Err(error)) if error.kind() != ErrorKind::BrokenPipe => {
+                return Err(Error::other(format!("Cannot write to `{command}`: {error}")));
+            }
+            _ => return Ok(String::from_utf8_lossy(&output).into_owned()),
+        }
+    }
+    let errors = String::from_utf8_lossy(&errors);
+    let message = match errors.lines().map(str::trim).find(|line| !line.is_empty()) {
+        Some(line) => format!("`{command}` failed: {line}"),
+        None => format!("`{command}` failed with {status}"),
+    };
+    Err(Error::other(message))
+}
+
+/// Reads all of `reader` in a new thread, which sends the bytes once done
+fn read_to_end(mut reader: impl Read + Send + 'static) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = reader.read_to_end(&mut bytes);
+        let _ = sender.send(bytes);
+    });
+    receiver
+}
+
+/// Waits for the value sent by a thread until `timeout` after `start`.
+/// Gives `None` if there is no thread or it stopped without sending one.
+fn receive_within<T>(
+    receiver: Option<Receiver<T>>,
+    start: Instant,
+    timeout: Duration,
+) -> Result<Option<T>, RecvTimeoutError> {
+    let Some(receiver) = receiver else {
+        return Ok(None);
+    };
+    match receiver.recv_timeout(timeout.saturating_sub(start.elapsed())) {
+        Ok(value) => Ok(Some(value)),
+        Err(RecvTimeoutError::Disconnected) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Sends the lines of `reader` to `sender` from a background thread,
+/// replacing invalid UTF-8
+fn read_lines(reader: impl Read + Send + 'static, sender: Sender<String>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).map_or(false, |read| read > 0) {
+            let text = String::from_utf8_lossy(&line);
+            if sender.send(text.trim_end_matches(['\n', '\r']).to_owned()).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_job() {
+        let mut job = Job::spawn("echo out; echo err >&2; exit 3").expect("sh is available");
+        let mut lines = Vec::new();
+        let start = Instant::now();
+        let status = loop {
+            lines.extend(job.poll());
+            if let Some(status) = job.status() {
+                break status;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "the job did not finish");
+            thread::sleep(Duration::from_millis(10));
+        };
+        lines.sort();
+        assert_eq!(lines, ["err", "out"]);
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_kill() {
+        // The background `sleep` keeps the output open until it is killed
+        let mut job = Job::spawn("sleep 30 & echo started; wait").expect("sh is available");
+        let start = Instant::now();
+        while job.poll().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5), "the job did not start");
+            thread::sleep(Duration::from_millis(10));
+        }
+        job.kill();
+        while job.status().is_none() {
+            job.poll();
+            assert!(start.elapsed() < Duration::from_secs(5), "the job was not killed");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(filter("sort", "b\na\n").expect("sort succeeds"), "a\nb\n");
+        let error = filter("echo bad >&2; exit 1", "").expect_err("exit 1 fails");
+        assert_eq!(error.to_string(), "`echo bad >&2; exit 1` failed: bad");
+        let error = filter_within("sleep 5", "", Duration::from_millis(50)).expect_err("sleep times out");
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        // A background process keeping the output open is stopped too,
+        let start = Instant::now();
+        let error = filter_within("sleep 5 & echo x", "", Duration::from_millis(50)).expect_err("sleep times out");
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // and so is one keeping the input open without reading it
+        let command = "exec 3<&0; sleep 5 <&3 >/dev/null 2>&1 & echo x";
+        let error = filter_within(command, &"x\n".repeat(100_000), Duration::from_millis(50)).expect_err("sleep times out");
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // Commands may leave their input unread
+        assert_eq!(filter("echo head", &"x\n".repeat(100_000)).expect("echo succeeds"), "head\n");
+    }
+}
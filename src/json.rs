@@ -0,0 +1,358 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+/// A JSON value, as exchanged with language servers
+#[derive(PartialEq, Clone, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// The members of an object, in the order they were written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a JSON document
+    ///
+    /// # Errors
+    /// It will return `Err` if `text` is not a single valid JSON value
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            index: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.index < parser.chars.len() {
+            return Err(parser.error("Unexpected data after the JSON value"));
+        }
+        Ok(value)
+    }
+
+    /// Builds an object from its members
+    #[must_use]
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Self)>) -> Self {
+        Self::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+        )
+    }
+
+    /// Returns the member `key` of an object
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match *self {
+            Self::Object(ref members) => members
+                .iter()
+                .find(|&&(ref name, _)| name == key)
+                .map(|&(_, ref value)| value),
+            _ => None,
+        }
+    }
+
+    /// Returns the string, if the value is one
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Self::String(ref value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the number as an index or a count, if the value is a
+    /// non-negative integer
+    #[must_use]
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            #[allow(clippy::as_conversions, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            Self::Number(value) if value >= 0.0 && value.fract() == 0.0 => Some(value as usize),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements, if the value is an array
+    #[must_use]
+    pub fn as_array(&self) -> Option<&[Self]> {
+        match *self {
+            Self::Array(ref elements) => Some(elements),
+            _ => None,
+        }
+    }
+
+    /// Checks if the value is `null`
+    #[must_use]
+    pub fn is_null(&self) -> bool {
+        *self == Self::Null
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<usize> for Json {
+    #[allow(clippy::as_conversions, clippy::cast_precision_loss)]
+    fn from(value: usize) -> Self {
+        Self::Number(value as f64)
+    }
+}
+
+impl fmt::Display for Json {
+    /// Writes the value as compact JSON
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => write!(f, "{value:.0}"),
+            Self::Number(value) => write!(f, "{value}"),
+            Self::String(ref value) => write_string(f, value),
+            Self::Array(ref elements) => {
+                write!(f, "[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(ref members) => {
+                write!(f, "{{")?;
+                for (index, &(ref key, ref value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Writes `value` as a quoted JSON string
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// A recursive descent parser over the characters of a JSON document
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{message} at character {}", self.index),
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.index = self.index.saturating_add(1);
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.index = self.index.saturating_add(1);
+        }
+    }
+
+    /// Consumes `literal` and returns `value`
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, Error> {
+        for expected in literal.chars() {
+            if self.next() != Some(expected) {
+                return Err(self.error("Invalid literal"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("Expected a JSON value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, Error> {
+        let start = self.index;
+        while self
+            .peek()
+            .map_or(false, |c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            self.index = self.index.saturating_add(1);
+        }
+        let text: String = self.chars.get(start..self.index).unwrap_or_default().iter().collect();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.next();
+        let mut result = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(result),
+                Some('\\') => match self.next() {
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('t') => result.push('\t'),
+                    Some('b') => result.push('\u{8}'),
+                    Some('f') => result.push('\u{c}'),
+                    Some('u') => result.push(self.unicode_escape()?),
+                    Some(c) => result.push(c),
+                    None => return Err(self.error("Unterminated string")),
+                },
+                Some(c) => result.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    /// Parses the digits of a `\u` escape, along with the low surrogate
+    /// that follows a high one
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let high = self.hex_digits()?;
+        if (0xD800..0xDC00).contains(&high) && self.peek() == Some('\\') {
+            self.index = self.index.saturating_add(1);
+            if self.next() == Some('u') {
+                let low = self.hex_digits()?;
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                return char::from_u32(code).ok_or_else(|| self.error("Invalid surrogate pair"));
+            }
+        }
+        Ok(char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, Error> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("Invalid unicode escape"))?;
+            value = value * 16 + digit;
+        }
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Json, Error> {
+        self.next();
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(Json::Array(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => (),
+                Some(']') => return Ok(Json::Array(elements)),
+                _ => return Err(self.error("Expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, Error> {
+        self.next();
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a member name"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.next() != Some(':') {
+                return Err(self.error("Expected `:`"));
+            }
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => (),
+                Some('}') => return Ok(Json::Object(members)),
+                _ => return Err(self.error("Expected `,` or `}`")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_write() {
+        let text = r#"{"id":1,"result":{"contents":"fn main()\n\"x\"","list":[true,null,-2.5e1]},"emoji":"\ud83d\ude00"}"#;
+        let value = Json::parse(text).expect("valid JSON");
+        assert_eq!(value.get("id").and_then(Json::as_usize), Some(1));
+        let result = value.get("result").expect("a result");
+        assert_eq!(result.get("contents").and_then(Json::as_str), Some("fn main()\n\"x\""));
+        assert_eq!(
+            result.get("list").and_then(Json::as_array),
+            Some(&[Json::Bool(true), Json::Null, Json::Number(-25.0)][..])
+        );
+        assert_eq!(value.get("emoji").and_then(Json::as_str), Some("\u{1f600}"));
+        assert_eq!(Json::parse(&value.to_string()).expect("valid JSON"), value);
+        assert_eq!(
+            Json::object([("a", Json::from(3)), ("b", Json::from("\t"))]).to_string(),
+            r#"{"a":3,"b":"\t"}"#
+        );
+        assert!(Json::parse("{\"a\":}").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::Config;
+use std::io::{Error, ErrorKind};
+use termion::event::Key;
+
+/// A named editor operation that can be bound to a key sequence
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[non_exhaustive]
+pub enum Action {
+    Quit,
+    Save,
+    Find,
+    Help,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    PageUp,
+    PageDown,
+    LineStart,
+    LineEnd,
+    DeleteForward,
+    DeleteBackward,
+    Command,
+    SwitchTheme,
+    SetFileType,
+    MatchBracket,
+    Select,
+    ToggleComment,
+    Tab,
+    Indent,
+    Outdent,
+    DuplicateLines,
+    DeleteLines,
+    MoveLinesUp,
+    MoveLinesDown,
+    JoinLines,
+    SortLines,
+    UniqueLines,
+    ConvertCase,
+    CompleteNext,
+    CompletePrevious,
+    Hover,
+    GoToDefinition,
+    LspComplete,
+    Build,
+    NextError,
+    PreviousError,
+    SearchFiles,
+    NextBuffer,
+    CloseBuffer,
+    Format,
+    Filter,
+    InsertOutput,
+    RunCommand,
+    KillCommand,
+    NextChange,
+    PreviousChange,
+    ShowDiff,
+    RevertHunk,
+    Reload,
+}
+
+/// Every action with its configuration name and a short description
+const ACTIONS: [(Action, &str, &str); 52] = [
+    (Action::Quit, "quit", "Quit the editor"),
+    (Action::Save, "save", "Save the file"),
+    (Action::Find, "find", "Search in the file"),
+    (Action::Help, "help", "Show the key bindings"),
+    (Action::MoveUp, "move_up", "Move the cursor up"),
+    (Action::MoveDown, "move_down", "Move the cursor down"),
+    (Action::MoveLeft, "move_left", "Move the cursor left"),
+    (Action::MoveRight, "move_right", "Move the cursor right"),
+    (Action::PageUp, "page_up", "Move one screen up"),
+    (Action::PageDown, "page_down", "Move one screen down"),
+    (Action::LineStart, "line_start", "Move to the start of the line"),
+    (Action::LineEnd, "line_end", "Move to the end of the line"),
+    (Action::DeleteForward, "delete_forward", "Delete the character under the cursor"),
+    (Action::DeleteBackward, "delete_backward", "Delete the character before the cursor"),
+    (Action::Command, "command", "Run an action by name"),
+    (Action::SwitchTheme, "theme", "Switch the color theme"),
+    (Action::SetFileType, "filetype", "Override the filetype of the file"),
+    (Action::MatchBracket, "match_bracket", "Jump to the matching bracket"),
+    (Action::Select, "select", "Start a selection at the cursor, or clear it"),
+    (Action::ToggleComment, "toggle_comment", "Comment or uncomment the current or selected lines"),
+    (Action::Tab, "tab", "Indent the selected lines, or insert an indentation"),
+    (Action::Indent, "indent", "Indent the current or selected lines"),
+    (Action::Outdent, "outdent", "Outdent the current or selected lines"),
+    (Action::DuplicateLines, "duplicate_lines", "Duplicate the current or selected lines"),
+    (Action::DeleteLines, "delete_lines", "Delete the current or selected lines"),
+    (Action::MoveLinesUp, "move_lines_up", "Move the current or selected lines up"),
+    (Action::MoveLinesDown, "move_lines_down", "Move the current or selected lines down"),
+    (Action::JoinLines, "join_lines", "Join the selected lines, or the current line with the next"),
+    (Action::SortLines, "sort", "Sort the lines, optionally `numeric`, `nocase` or `reverse`"),
+    (Action::UniqueLines, "unique", "Remove duplicate lines"),
+    (Action::ConvertCase, "case", "Convert to upper, lower, title, snake or camel case"),
+    (Action::CompleteNext, "complete_next", "Complete the word with the next match in the file"),
+    (Action::CompletePrevious, "complete_previous", "Complete the word with the previous match in the file"),
+    (Action::Hover, "hover", "Show the language server documentation of the symbol"),
+    (Action::GoToDefinition, "definition", "Go to the definition of the symbol"),
+    (Action::LspComplete, "lsp_complete", "Complete with the suggestions of the language server"),
+    (Action::Build, "build", "Save and run the build command, or the command given"),
+    (Action::NextError, "next_error", "Go to the next error of the last build"),
+    (Action::PreviousError, "previous_error", "Go to the previous error of the last build"),
+    (Action::SearchFiles, "search_files", "Search in the files of the project"),
+    (Action::NextBuffer, "next_buffer", "Show the next open buffer"),
+    (Action::CloseBuffer, "close_buffer", "Close the current buffer"),
+    (Action::Format, "format", "Format the file with the formatter of its language"),
+    (Action::Filter, "filter", "Pipe the selection or the file through a shell command"),
+    (Action::InsertOutput, "insert_output", "Insert the output of a shell command"),
+    (Action::RunCommand, "run", "Run a shell command and show its output"),
+    (Action::KillCommand, "kill", "Stop the running shell command"),
+    (Action::NextChange, "next_change", "Go to the next change since the last commit"),
+    (Action::PreviousChange, "previous_change", "Go to the previous change since the last commit"),
+    (Action::ShowDiff, "diff", "Compare the text with the file on disk"),
+    (Action::RevertHunk, "revert_hunk", "Put back the saved version of the changed lines at the cursor"),
+    (Action::Reload, "reload", "Read the file again, dropping the unsaved changes"),
+];
+
+impl Action {
+    /// Returns the name used for the action in the configuration
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|&&(action, _, _)| action == self)
+            .map_or("", |&(_, name, _)| name)
+    }
+
+    /// Returns a short human readable description of the action
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|&&(action, _, _)| action == self)
+            .map_or("", |&(_, _, description)| description)
+    }
+
+    /// Checks if the action changes the text of the document
+    #[must_use]
+    pub fn is_edit(self) -> bool {
+        matches!(
+            self,
+            Self::DeleteForward
+                | Self::DeleteBackward
+                | Self::ToggleComment
+                | Self::Tab
+                | Self::Indent
+                | Self::Outdent
+                | Self::DuplicateLines
+                | Self::DeleteLines
+                | Self::MoveLinesUp
+                | Self::MoveLinesDown
+                | Self::JoinLines
+                | Self::SortLines
+                | Self::UniqueLines
+                | Self::ConvertCase
+                | Self::CompleteNext
+                | Self::CompletePrevious
+                | Self::LspComplete
+                | Self::Format
+                | Self::Filter
+                | Self::InsertOutput
+        )
+    }
+
+    /// Returns every action
+    pub fn all() -> impl Iterator<Item = Self> {
+        ACTIONS.iter().map(|&(action, _, _)| action)
+    }
+
+    /// Returns the action with the given configuration name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|&&(_, action_name, _)| action_name == name)
+            .map(|&(action, _, _)| action)
+    }
+}
+
+/// The result of looking up a sequence of pressed keys in a `Keymap`
+#[derive(PartialEq, Eq, Debug)]
+pub enum Lookup {
+    /// The keys are bound to an action
+    Action(Action),
+    /// The keys are the beginning of a longer chord
+    Pending,
+    /// No binding starts with the keys
+    Unbound,
+}
+
+/// Maps key chords (one or more keys pressed in sequence) to actions
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: Vec::new(),
+        };
+        for (key, action) in [
+            (Key::Ctrl('t'), Action::Quit),
+            (Key::Ctrl('s'), Action::Save),
+            (Key::Ctrl('f'), Action::Find),
+            (Key::F(1), Action::Help),
+            (Key::Up, Action::MoveUp),
+            (Key::Down, Action::MoveDown),
+            (Key::Left, Action::MoveLeft),
+            (Key::Right, Action::MoveRight),
+            (Key::PageUp, Action::PageUp),
+            (Key::PageDown, Action::PageDown),
+            (Key::Home, Action::LineStart),
+            (Key::End, Action::LineEnd),
+            (Key::Delete, Action::DeleteForward),
+            (Key::Backspace, Action::DeleteBackward),
+            (Key::Alt('x'), Action::Command),
+            (Key::Ctrl('b'), Action::MatchBracket),
+            (Key::Null, Action::Select),
+            (Key::Alt('/'), Action::ToggleComment),
+            (Key::Char('\t'), Action::Tab),
+            (Key::BackTab, Action::Outdent),
+            (Key::Alt('d'), Action::DuplicateLines),
+            (Key::Ctrl('k'), Action::DeleteLines),
+            (Key::Alt('k'), Action::MoveLinesUp),
+            (Key::Alt('j'), Action::MoveLinesDown),
+            (Key::Alt('J'), Action::JoinLines),
+            (Key::Ctrl('n'), Action::CompleteNext),
+            (Key::Ctrl('p'), Action::CompletePrevious),
+            (Key::Alt('h'), Action::Hover),
+            (Key::F(12), Action::GoToDefinition),
+            (Key::Alt('c'), Action::LspComplete),
+            (Key::F(5), Action::Build),
+            (Key::Alt('n'), Action::NextError),
+            (Key::Alt('p'), Action::PreviousError),
+            (Key::Alt('f'), Action::SearchFiles),
+            (Key::Alt('b'), Action::NextBuffer),
+            (Key::Alt('w'), Action::CloseBuffer),
+            (Key::Alt('l'), Action::Format),
+            (Key::Alt('|'), Action::Filter),
+            (Key::Alt('!'), Action::InsertOutput),
+            (Key::Alt('r'), Action::RunCommand),
+            (Key::Ctrl('c'), Action::KillCommand),
+            (Key::Alt('g'), Action::NextChange),
+            (Key::Alt('G'), Action::PreviousChange),
+            (Key::Alt('v'), Action::ShowDiff),
+            (Key::Alt('u'), Action::RevertHunk),
+        ] {
+            keymap.bind(vec![key], action);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Binds `keys` to `action`, removing any binding that would make one
+    /// of the two chords unreachable.
+    pub fn bind(&mut self, keys: Vec<Key>, action: Action) {
+        self.unbind(&keys);
+        self.bindings.push((keys, action));
+    }
+
+    /// Removes the binding of `keys` together with every binding that
+    /// starts with `keys` or that `keys` starts with.
+    pub fn unbind(&mut self, keys: &[Key]) {
+        self.bindings
+            .retain(|&(ref bound, _)| !bound.starts_with(keys) && !keys.starts_with(bound));
+    }
+
+    /// Looks up the keys pressed so far
+    #[must_use]
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        let mut pending = false;
+        for &(ref bound, action) in &self.bindings {
+            if bound == keys {
+                return Lookup::Action(action);
+            }
+            if bound.starts_with(keys) {
+                pending = true;
+            }
+        }
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    /// Returns the first chord bound to `action`
+    #[must_use]
+    pub fn keys_for(&self, action: Action) -> Option<&[Key]> {
+        self.bindings
+            .iter()
+            .find(|&&(_, bound_action)| bound_action == action)
+            .map(|&(ref keys, _)| keys.as_slice())
+    }
+
+    /// Returns every binding, in the order they were made
+    pub fn bindings(&self) -> impl Iterator<Item = (&[Key], Action)> {
+        self.bindings
+            .iter()
+            .map(|&(ref keys, action)| (keys.as_slice(), action))
+    }
+
+    /// Applies the `[keys]` sections of the configuration, where each entry
+    /// binds a chord to an action name, or unbinds it with `none`.
+    ///
+    /// # Errors
+    /// It will return `Err` on the first chord or action name that cannot be
+    /// parsed. Bindings before it are still applied.
+    pub fn configure(&mut self, config: &Config) -> Result<(), Error> {
+        for section in config.sections("keys") {
+            for &(ref chord, ref action_name) in section.entries() {
+                let keys = parse_chord(chord)?;
+                if action_name == "none" {
+                    self.unbind(&keys);
+                } else if let Some(action) = Action::from_name(action_name) {
+                    self.bind(keys, action);
+                } else {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unknown action `{action_name}` bound to `{chord}`"),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a chord such as `Ctrl-K Ctrl-C`, where the keys pressed in
+/// sequence are separated by whitespace.
+///
+/// # Errors
+/// It will return `Err` if the chord is empty or a key name is unknown
+pub fn parse_chord(chord: &str) -> Result<Vec<Key>, Error> {
+    let keys = chord
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<Vec<Key>, Error>>()?;
+    if keys.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "Empty key chord"));
+    }
+    Ok(keys)
+}
+
+fn parse_key(name: &str) -> Result<Key, Error> {
+    let single_char = |rest: &str| {
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    let key = match name {
+        "Up" => Some(Key::Up),
+        "Down" => Some(Key::Down),
+        "Left" => Some(Key::Left),
+        "Right" => Some(Key::Right),
+        "Home" => Some(Key::Home),
+        "End" => Some(Key::End),
+        "PageUp" => Some(Key::PageUp),
+        "PageDown" => Some(Key::PageDown),
+        "Backspace" => Some(Key::Backspace),
+        "Delete" => Some(Key::Delete),
+        "Insert" => Some(Key::Insert),
+        "Tab" => Some(Key::Char('\t')),
+        "BackTab" | "Shift-Tab" => Some(Key::BackTab),
+        "Enter" => Some(Key::Char('\n')),
+        "Space" => Some(Key::Char(' ')),
+        "Esc" => Some(Key::Esc),
+        "Ctrl-Space" => Some(Key::Null),
+        _ => {
+            if let Some(rest) = name.strip_prefix("Ctrl-") {
+                single_char(rest).map(|c| Key::Ctrl(c.to_ascii_lowercase()))
+            } else if let Some(rest) = name.strip_prefix("Alt-") {
+                single_char(rest).map(Key::Alt)
+            } else if let Some(number) = name.strip_prefix('F').and_then(|n| n.parse().ok()) {
+                Some(Key::F(number)).filter(|_| (1..=12).contains(&number))
+            } else {
+                single_char(name).map(Key::Char)
+            }
+        }
+    };
+    key.ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unknown key `{name}`")))
+}
+
+/// Formats a chord the way it is written in the configuration
+#[must_use]
+pub fn format_chord(keys: &[Key]) -> String {
+    keys.iter().map(|&key| format_key(key)).collect::<Vec<String>>().join(" ")
+}
+
+fn format_key(key: Key) -> String {
+    match key {
+        Key::Up => "Up".to_owned(),
+        Key::Down => "Down".to_owned(),
+        Key::Left => "Left".to_owned(),
+        Key::Right => "Right".to_owned(),
+        Key::Home => "Home".to_owned(),
+        Key::End => "End".to_owned(),
+        Key::PageUp => "PageUp".to_owned(),
+        Key::PageDown => "PageDown".to_owned(),
+        Key::Backspace => "Backspace".to_owned(),
+        Key::Delete => "Delete".to_owned(),
+        Key::Insert => "Insert".to_owned(),
+        Key::BackTab => "Shift-Tab".to_owned(),
+        Key::Esc => "Esc".to_owned(),
+        Key::Null => "Ctrl-Space".to_owned(),
+        Key::Char('\t') => "Tab".to_owned(),
+        Key::Char('\n') => "Enter".to_owned(),
+        Key::Char(' ') => "Space".to_owned(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c.to_ascii_uppercase()),
+        Key::Alt(c) => format!("Alt-{c}"),
+        Key::F(number) => format!("F{number}"),
+        _ => "?".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!(
+            parse_chord("Ctrl-K Ctrl-c").expect("valid chord"),
+            vec![Key::Ctrl('k'), Key::Ctrl('c')]
+        );
+        assert_eq!(parse_chord("F5").expect("valid chord"), vec![Key::F(5)]);
+        assert_eq!(format_chord(&[Key::Ctrl('k'), Key::Char('\t')]), "Ctrl-K Tab");
+        assert!(parse_chord("Ctrl-Foo").is_err());
+        assert!(parse_chord("").is_err());
+    }
+
+    #[test]
+    fn test_lookup_chord() {
+        let mut keymap = Keymap::default();
+        keymap.bind(vec![Key::Ctrl('k'), Key::Ctrl('c')], Action::Help);
+        assert_eq!(keymap.lookup(&[Key::Ctrl('k')]), Lookup::Pending);
+        assert_eq!(
+            keymap.lookup(&[Key::Ctrl('k'), Key::Ctrl('c')]),
+            Lookup::Action(Action::Help)
+        );
+        assert_eq!(keymap.lookup(&[Key::Ctrl('k'), Key::Ctrl('x')]), Lookup::Unbound);
+
+        let config = Config::parse("[keys]\nCtrl-Q = quit\nCtrl-T = none\n").expect("valid config");
+        keymap.configure(&config).expect("valid bindings");
+        assert_eq!(keymap.lookup(&[Key::Ctrl('q')]), Lookup::Action(Action::Quit));
+        assert_eq!(keymap.lookup(&[Key::Ctrl('t')]), Lookup::Unbound);
+        assert_eq!(keymap.keys_for(Action::Quit), Some(&[Key::Ctrl('q')][..]));
+    }
+}
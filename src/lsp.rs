@@ -0,0 +1,726 @@
+use crate::json::Json;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the server gets to read the last messages when the client is
+/// dropped, before it is killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A position in a document as language servers count it: a line, and a
+/// column in UTF-16 code units
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+impl Position {
+    fn to_json(self) -> Json {
+        Json::object([
+            ("line", Json::from(self.line)),
+            ("character", Json::from(self.character)),
+        ])
+    }
+
+    fn from_json(value: &Json) -> Option<Self> {
+        Some(Self {
+            line: value.get("line")?.as_usize()?,
+            character: value.get("character")?.as_usize()?,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// Returns the letter marking the severity in the gutter
+    #[must_use]
+    pub fn marker(self) -> char {
+        match self {
+            Self::Error => 'E',
+            Self::Warning => 'W',
+            Self::Information => 'I',
+            Self::Hint => 'H',
+        }
+    }
+}
+
+/// A problem the language server reported in a document
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub start: Position,
+    pub end: Position,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn from_json(value: &Json) -> Option<Self> {
+        let range = value.get("range")?;
+        let severity = match value.get("severity").and_then(Json::as_usize) {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            _ => Severity::Error,
+        };
+        Some(Self {
+            start: Position::from_json(range.get("start")?)?,
+            end: Position::from_json(range.get("end")?)?,
+            severity,
+            message: value.get("message")?.as_str()?.to_owned(),
+        })
+    }
+}
+
+/// What the language server sent, as returned by `Client::poll`
+#[derive(Debug)]
+pub enum Event {
+    /// The diagnostics of the document at `uri`, replacing the previous ones
+    Diagnostics { uri: String, diagnostics: Vec<Diagnostic> },
+    /// The documentation of the symbol hovered, as plain text or markdown
+    Hover(String),
+    /// Where the symbol asked for is defined
+    Definition { uri: String, position: Position },
+    /// The completion candidates, in the order the server ranked them
+    Completion(Vec<String>),
+    /// A request failed, or the server stopped
+    Error(String),
+}
+
+/// The requests waiting for a response
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Request {
+    Initialize,
+    Hover,
+    Definition,
+    Completion,
+    Shutdown,
+}
+
+/// A client talking JSON-RPC to a language server. Background threads write
+/// the messages to its input and read the ones of its output, so that a
+/// busy server never blocks the editor.
+pub struct Client {
+    /// The messages left to write
+    outgoing: Sender<Json>,
+    /// The thread writing the messages, done once `outgoing` is closed
+    writer: JoinHandle<()>,
+    messages: Receiver<Result<Json, Error>>,
+    next_id: usize,
+    pending: Vec<(usize, Request)>,
+    /// Whether the server answered the `initialize` request. Until then,
+    /// messages are kept in `queued`.
+    initialized: bool,
+    queued: Vec<Json>,
+    /// The server process, stopped when the client is dropped
+    child: Option<Child>,
+}
+
+impl Client {
+    /// Starts the language server `command`, made of a program and its
+    /// arguments separated by spaces, in the project at `root`
+    ///
+    /// # Errors
+    /// It will return `Err` if the command is empty or cannot be started
+    pub fn spawn(command: &str, root: &Path) -> Result<Self, Error> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Empty language server command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::new(ErrorKind::Other, "The language server has no pipes"));
+        };
+        let mut client = Self::new(stdout, stdin, root)?;
+        client.child = Some(child);
+        Ok(client)
+    }
+
+    /// Starts a session with the server, writing to it through `writer`
+    /// and reading its messages from `reader`, for the project at `root`.
+    /// A failed write is received as an error message.
+    ///
+    /// # Errors
+    /// It will return `Err` if the `initialize` request cannot be sent
+    pub fn new<R, W>(reader: R, mut writer: W, root: &Path) -> Result<Self, Error>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let (sender, messages) = mpsc::channel();
+        let (outgoing, to_write) = mpsc::channel::<Json>();
+        let errors = sender.clone();
+        let writer_thread = thread::spawn(move || {
+            for message in to_write {
+                if let Err(error) = write_message(&mut writer, &message) {
+                    let _ = errors.send(Err(error));
+                    break;
+                }
+            }
+        });
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            loop {
+                let message = read_message(&mut reader);
+                let stop = !matches!(message, Ok(Some(_)));
+                let message = message.and_then(|message| {
+                    message.ok_or_else(|| {
+                        Error::new(ErrorKind::UnexpectedEof, "The language server stopped")
+                    })
+                });
+                if sender.send(message).is_err() || stop {
+                    break;
+                }
+            }
+        });
+        let mut client = Self {
+            outgoing,
+            writer: writer_thread,
+            messages,
+            next_id: 0,
+            pending: Vec::new(),
+            initialized: false,
+            queued: Vec::new(),
+            child: None,
+        };
+        let capabilities = Json::object([
+            (
+                "textDocument",
+                Json::object([
+                    ("synchronization", Json::object([("didSave", Json::from(true))])),
+                    (
+                        "hover",
+                        Json::object([(
+                            "contentFormat",
+                            Json::Array(vec![Json::from("plaintext"), Json::from("markdown")]),
+                        )]),
+                    ),
+                    (
+                        "completion",
+                        Json::object([(
+                            "completionItem",
+                            Json::object([("snippetSupport", Json::from(false))]),
+                        )]),
+                    ),
+                    ("definition", Json::object([])),
+                    ("publishDiagnostics", Json::object([])),
+                ]),
+            ),
+        ]);
+        let params = Json::object([
+            ("processId", Json::from(usize::try_from(std::process::id()).unwrap_or_default())),
+            ("rootUri", Json::from(path_to_uri(root))),
+            ("capabilities", capabilities),
+        ]);
+        let message = client.request(Request::Initialize, "initialize", params);
+        client.write(message)?;
+        Ok(client)
+    }
+
+    /// Tells the server that a document was opened with `text`
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn did_open(&mut self, uri: &str, language_id: &str, version: usize, text: &str) -> Result<(), Error> {
+        let document = Json::object([
+            ("uri", Json::from(uri)),
+            ("languageId", Json::from(language_id)),
+            ("version", Json::from(version)),
+            ("text", Json::from(text)),
+        ]);
+        self.notify("textDocument/didOpen", Json::object([("textDocument", document)]))
+    }
+
+    /// Sends the whole `text` of a changed document
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn did_change(&mut self, uri: &str, version: usize, text: &str) -> Result<(), Error> {
+        let document = Json::object([("uri", Json::from(uri)), ("version", Json::from(version))]);
+        let change = Json::object([("text", Json::from(text))]);
+        self.notify(
+            "textDocument/didChange",
+            Json::object([
+                ("textDocument", document),
+                ("contentChanges", Json::Array(vec![change])),
+            ]),
+        )
+    }
+
+    /// Tells the server that a document was saved
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn did_save(&mut self, uri: &str) -> Result<(), Error> {
+        self.notify("textDocument/didSave", text_document(uri))
+    }
+
+    /// Tells the server that a document was closed
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn did_close(&mut self, uri: &str) -> Result<(), Error> {
+        self.notify("textDocument/didClose", text_document(uri))
+    }
+
+    /// Asks for the documentation of the symbol at `position`, returned as
+    /// an `Event::Hover`
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn hover(&mut self, uri: &str, position: Position) -> Result<(), Error> {
+        self.send_request(Request::Hover, "textDocument/hover", uri, position)
+    }
+
+    /// Asks where the symbol at `position` is defined, returned as an
+    /// `Event::Definition`
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn definition(&mut self, uri: &str, position: Position) -> Result<(), Error> {
+        self.send_request(Request::Definition, "textDocument/definition", uri, position)
+    }
+
+    /// Asks for the completions at `position`, returned as an
+    /// `Event::Completion`
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn completion(&mut self, uri: &str, position: Position) -> Result<(), Error> {
+        self.send_request(Request::Completion, "textDocument/completion", uri, position)
+    }
+
+    /// Asks the server to shut down, then to exit
+    ///
+    /// # Errors
+    /// It will return `Err` if the server cannot be written to
+    pub fn shutdown(&mut self) -> Result<(), Error> {
+        let message = self.request(Request::Shutdown, "shutdown", Json::Null);
+        self.write(message)?;
+        self.write(notification("exit", Json::Null))
+    }
+
+    /// Returns the events received since the last call, without waiting
+    /// for more. Requests from the server are answered on the way.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match self.messages.try_recv() {
+                Ok(Ok(message)) => {
+                    if let Err(error) = self.handle(&message, &mut events) {
+                        events.push(Event::Error(error.to_string()));
+                    }
+                }
+                Ok(Err(error)) => events.push(Event::Error(error.to_string())),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return events,
+            }
+        }
+    }
+
+    fn handle(&mut self, message: &Json, events: &mut Vec<Event>) -> Result<(), Error> {
+        let id = message.get("id").cloned();
+        match (message.get("method").and_then(Json::as_str), id) {
+            (Some(method), Some(id)) => {
+                // Requests from the server are answered with an empty
+                // result, except for the configuration it asks for, which
+                // gets a default for each item.
+                let result = if method == "workspace/configuration" {
+                    let items = message
+                        .get("params")
+                        .and_then(|params| params.get("items"))
+                        .and_then(Json::as_array)
+                        .map_or(0, <[Json]>::len);
+                    Json::Array(vec![Json::Null; items])
+                } else {
+                    Json::Null
+                };
+                self.write(Json::object([
+                    ("jsonrpc", Json::from("2.0")),
+                    ("id", id),
+                    ("result", result),
+                ]))
+            }
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let params = message.get("params");
+                if let Some(uri) = params.and_then(|params| params.get("uri")).and_then(Json::as_str) {
+                    let diagnostics = params
+                        .and_then(|params| params.get("diagnostics"))
+                        .and_then(Json::as_array)
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(Diagnostic::from_json)
+                        .collect();
+                    events.push(Event::Diagnostics {
+                        uri: uri.to_owned(),
+                        diagnostics,
+                    });
+                }
+                Ok(())
+            }
+            (Some(_), None) => Ok(()),
+            (None, Some(id)) => self.handle_response(id.as_usize(), message, events),
+            (None, None) => Err(Error::new(ErrorKind::InvalidData, "Invalid message from the language server")),
+        }
+    }
+
+    fn handle_response(&mut self, id: Option<usize>, message: &Json, events: &mut Vec<Event>) -> Result<(), Error> {
+        let Some(index) = self.pending.iter().position(|&(pending, _)| Some(pending) == id) else {
+            return Ok(());
+        };
+        let (_, request) = self.pending.remove(index);
+        if let Some(error) = message.get("error") {
+            let text = error.get("message").and_then(Json::as_str).unwrap_or("unknown error");
+            events.push(Event::Error(format!("Language server error: {text}")));
+            return Ok(());
+        }
+        let result = message.get("result").unwrap_or(&Json::Null);
+        match request {
+            Request::Initialize => {
+                self.initialized = true;
+                self.write(notification("initialized", Json::object([])))?;
+                for message in mem::take(&mut self.queued) {
+                    self.write(message)?;
+                }
+            }
+            Request::Hover => {
+                let text = hover_text(result.get("contents").unwrap_or(&Json::Null));
+                if !text.trim().is_empty() {
+                    events.push(Event::Hover(text));
+                }
+            }
+            Request::Definition => {
+                // The result is a location, a list of them, or a list of
+                // links to them.
+                let location = result.as_array().and_then(<[Json]>::first).unwrap_or(result);
+                let uri = location.get("uri").or_else(|| location.get("targetUri"));
+                let range = location
+                    .get("range")
+                    .or_else(|| location.get("targetSelectionRange"));
+                if let (Some(uri), Some(position)) = (
+                    uri.and_then(Json::as_str),
+                    range.and_then(|range| range.get("start")).and_then(Position::from_json),
+                ) {
+                    events.push(Event::Definition {
+                        uri: uri.to_owned(),
+                        position,
+                    });
+                }
+            }
+            Request::Completion => {
+                // The result is a list of items, or a list with a flag
+                // telling whether it is complete.
+                let items = result
+                    .as_array()
+                    .or_else(|| result.get("items").and_then(Json::as_array))
+                    .unwrap_or_default();
+                let candidates = items
+                    .iter()
+                    .filter_map(|item| {
+                        item.get("textEdit")
+                            .and_then(|edit| edit.get("newText"))
+                            .or_else(|| item.get("insertText"))
+                            .or_else(|| item.get("label"))
+                            .and_then(Json::as_str)
+                            .map(str::to_owned)
+                    })
+                    .collect();
+                events.push(Event::Completion(candidates));
+            }
+            Request::Shutdown => (),
+        }
+        Ok(())
+    }
+
+    fn send_request(&mut self, request: Request, method: &str, uri: &str, position: Position) -> Result<(), Error> {
+        let mut params = text_document(uri);
+        if let Json::Object(ref mut members) = params {
+            members.push(("position".to_owned(), position.to_json()));
+        }
+        let message = self.request(request, method, params);
+        self.send(message)
+    }
+
+    /// Builds a request and remembers that it waits for a response
+    fn request(&mut self, request: Request, method: &str, params: Json) -> Json {
+        let id = self.next_id;
+        self.next_id = self.next_id.saturating_add(1);
+        self.pending.push((id, request));
+        let mut members = vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", Json::from(id)),
+            ("method", Json::from(method)),
+        ];
+        if !params.is_null() {
+            members.push(("params", params));
+        }
+        Json::object(members)
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> Result<(), Error> {
+        self.send(notification(method, params))
+    }
+
+    /// Writes `message`, or keeps it until the server is initialized
+    fn send(&mut self, message: Json) -> Result<(), Error> {
+        if self.initialized {
+            self.write(message)
+        } else {
+            self.queued.push(message);
+            Ok(())
+        }
+    }
+
+    /// Hands `message` to the writer thread
+    fn write(&mut self, message: Json) -> Result<(), Error> {
+        self.outgoing
+            .send(message)
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "The language server stopped"))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = self.shutdown();
+            // Closing the channel lets the writer thread finish once the
+            // server read the last messages, which it gets a moment for
+            drop(mem::replace(&mut self.outgoing, mpsc::channel().0));
+            let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+            while !self.writer.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    let mut members = vec![("jsonrpc", Json::from("2.0")), ("method", Json::from(method))];
+    if !params.is_null() {
+        members.push(("params", params));
+    }
+    Json::object(members)
+}
+
+fn text_document(uri: &str) -> Json {
+    Json::object([("textDocument", Json::object([("uri", Json::from(uri))]))])
+}
+
+/// Returns the text of hover contents, which are a string, a marked string
+/// with a language, markup content, or a list of those
+fn hover_text(contents: &Json) -> String {
+    match *contents {
+        Json::String(ref text) => text.clone(),
+        Json::Array(ref parts) => parts
+            .iter()
+            .map(hover_text)
+            .collect::<Vec<String>>()
+            .join("\n"),
+        _ => contents
+            .get("value")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_owned(),
+    }
+}
+
+/// Writes `message` with the `Content-Length` header of the base protocol
+///
+/// # Errors
+/// It will return `Err` if `writer` fails
+pub fn write_message(writer: &mut impl Write, message: &Json) -> Result<(), Error> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Reads the next message of the base protocol, or `None` at the end of
+/// the input
+///
+/// # Errors
+/// It will return `Err` if `reader` fails or the message is malformed
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Json>, Error> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length: usize =
+        length.ok_or_else(|| Error::new(ErrorKind::InvalidData, "Message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+    Json::parse(&body).map(Some)
+}
+
+/// Returns the `file://` URI of `path`, made absolute from the current
+/// directory, with the characters URIs do not allow percent-encoded
+#[must_use]
+pub fn path_to_uri(path: &Path) -> String {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+    let mut uri = "file://".to_owned();
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+/// Returns the path of a `file://` URI, or `None` for other schemes
+#[must_use]
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut index = 0;
+    while let Some(&byte) = encoded.get(index) {
+        let escaped = encoded
+            .get(index.saturating_add(1)..index.saturating_add(3))
+            .filter(|_| byte == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(escaped) = escaped {
+            bytes.push(escaped);
+            index = index.saturating_add(3);
+        } else {
+            bytes.push(byte);
+            index = index.saturating_add(1);
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::os::unix::net::UnixStream;
+
+    /// Returns the next event of `client`, polling it for at most a few
+    /// seconds. Events received along with it are kept in `received`.
+    fn next_event(client: &mut Client, received: &mut VecDeque<Event>) -> Event {
+        let start = Instant::now();
+        loop {
+            received.extend(client.poll());
+            if let Some(event) = received.pop_front() {
+                return event;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "no event from the server");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_uri() {
+        let path = Path::new("/tmp/my file#1.rs");
+        assert_eq!(path_to_uri(path), "file:///tmp/my%20file%231.rs");
+        assert_eq!(uri_to_path(&path_to_uri(path)).as_deref(), Some(path));
+        assert_eq!(uri_to_path("https://example.com"), None);
+    }
+
+    #[test]
+    fn test_mock_server() {
+        let (client_end, server_end) = UnixStream::pair().expect("a socket pair");
+        let client_writer = client_end.try_clone().expect("a socket");
+        let mut server_writer = server_end.try_clone().expect("a socket");
+        let server = thread::spawn(move || {
+            let mut reader = BufReader::new(server_end);
+            let mut receive = || read_message(&mut reader).expect("a valid message").expect("a message");
+            let method = |message: &Json| message.get("method").and_then(Json::as_str).map(str::to_owned);
+
+            let initialize = receive();
+            assert_eq!(method(&initialize).as_deref(), Some("initialize"));
+            let id = initialize.get("id").cloned().expect("an id");
+            let response = Json::object([("id", id), ("result", Json::object([("capabilities", Json::object([]))]))]);
+            write_message(&mut server_writer, &response).expect("written");
+            assert_eq!(method(&receive()).as_deref(), Some("initialized"));
+
+            let open = receive();
+            assert_eq!(method(&open).as_deref(), Some("textDocument/didOpen"));
+            let uri = open
+                .get("params")
+                .and_then(|params| params.get("textDocument"))
+                .and_then(|document| document.get("uri"))
+                .cloned()
+                .expect("a uri");
+            let diagnostics = Json::parse(
+                r#"[{"range":{"start":{"line":0,"character":3},"end":{"line":0,"character":7}},"severity":2,"message":"unused"}]"#,
+            )
+            .expect("valid JSON");
+            let publish = notification(
+                "textDocument/publishDiagnostics",
+                Json::object([("uri", uri), ("diagnostics", diagnostics)]),
+            );
+            write_message(&mut server_writer, &publish).expect("written");
+
+            let hover = receive();
+            assert_eq!(method(&hover).as_deref(), Some("textDocument/hover"));
+            let contents = Json::object([("kind", Json::from("plaintext")), ("value", Json::from("fn main()"))]);
+            let response = Json::object([
+                ("id", hover.get("id").cloned().expect("an id")),
+                ("result", Json::object([("contents", contents)])),
+            ]);
+            write_message(&mut server_writer, &response).expect("written");
+        });
+
+        let mut client = Client::new(client_end, client_writer, Path::new("/project")).expect("initialized");
+        let mut received = VecDeque::new();
+        client
+            .did_open("file:///project/main.rs", "rust", 0, "fn main() {}\n")
+            .expect("sent");
+        match next_event(&mut client, &mut received) {
+            Event::Diagnostics { uri, diagnostics } => {
+                assert_eq!(uri, "file:///project/main.rs");
+                let diagnostic = diagnostics.first().expect("a diagnostic");
+                assert_eq!(diagnostic.severity, Severity::Warning);
+                assert_eq!(diagnostic.start, Position { line: 0, character: 3 });
+                assert_eq!(diagnostic.message, "unused");
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+        client
+            .hover("file:///project/main.rs", Position { line: 0, character: 4 })
+            .expect("sent");
+        match next_event(&mut client, &mut received) {
+            Event::Hover(text) => assert_eq!(text, "fn main()"),
+            event => panic!("unexpected event {event:?}"),
+        }
+        server.join().expect("the mock server succeeded");
+        assert!(matches!(next_event(&mut client, &mut received), Event::Error(_)));
+    }
+}
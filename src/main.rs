@@ -11,21 +11,41 @@
     clippy::blanket_clippy_restriction_lints,
     clippy::too_many_lines
 )]
+mod build;
+mod completion;
+mod config;
+mod diff;
 mod document;
 mod editor;
 mod filetype;
+mod git;
+mod glob;
 mod highlighting;
+mod ignore;
+mod job;
+mod json;
+mod keymap;
+mod lsp;
+mod pairs;
 mod row;
+mod search;
+mod style;
 mod terminal;
+mod theme;
+mod transform;
 
+pub use config::Config;
 pub use document::Document;
 use editor::Editor;
 pub use editor::Position;
 pub use editor::SearchDirection;
 pub use filetype::FileType;
 pub use filetype::HighlightingOptions;
+pub use keymap::Action;
+pub use keymap::Keymap;
 pub use row::Row;
 pub use terminal::Terminal;
+pub use theme::Theme;
 
 fn main() {
     Editor::default().run();
@@ -0,0 +1,81 @@
+use crate::row::BRACKETS;
+use crate::HighlightingOptions;
+
+/// Returns the character closing `c` if typing `c` opens a pair, given the
+/// characters `before` and `after` the cursor and whether the cursor is in
+/// a string or a comment.
+///
+/// Brackets are paired unless the cursor is right before a word. Quotes
+/// are paired outside of strings and comments, and not after a word, so
+/// that `don't` or `b"` stay as typed. In languages with lifetimes, a `'`
+/// after `&` or `<` starts one and is left alone too.
+#[must_use]
+pub fn closing(
+    c: char,
+    opts: &HighlightingOptions,
+    before: Option<char>,
+    after: Option<char>,
+    in_string_or_comment: bool,
+) -> Option<char> {
+    let free_after = after.map_or(true, |after| {
+        after.is_whitespace() || is_closing(after, opts) || matches!(after, ',' | ';')
+    });
+    if !free_after {
+        return None;
+    }
+    if let Some(&(_, close)) = BRACKETS.iter().find(|&&(open, _)| open == c) {
+        return Some(close);
+    }
+    if !is_quote(c, opts) || in_string_or_comment {
+        return None;
+    }
+    let after_word = before.map_or(false, |before| before.is_alphanumeric() || before == '_');
+    let lifetime = c == '\'' && opts.lifetimes() && matches!(before, Some('&' | '<'));
+    if after_word || lifetime {
+        return None;
+    }
+    Some(c)
+}
+
+/// Checks if `c` closes a pair, and may be typed over when it was inserted
+/// automatically
+#[must_use]
+pub fn is_closing(c: char, opts: &HighlightingOptions) -> bool {
+    BRACKETS.iter().any(|&(_, close)| close == c) || is_quote(c, opts)
+}
+
+/// Checks if `open` and `close` form a pair
+#[must_use]
+pub fn is_pair(open: char, close: char, opts: &HighlightingOptions) -> bool {
+    BRACKETS.contains(&(open, close)) || (open == close && is_quote(open, opts))
+}
+
+/// Checks if `c` delimits strings or characters in the language
+fn is_quote(c: char, opts: &HighlightingOptions) -> bool {
+    opts.string_delimiters().contains(&c) || (c == '\'' && opts.characters())
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use crate::FileType;
+
+    #[test]
+    fn test_closing() {
+        let rust = FileType::by_name("rust").expect("Rust is built in");
+        let opts = rust.highlighting_options();
+        assert_eq!(closing('(', opts, Some('f'), None, false), Some(')'));
+        assert_eq!(closing('{', opts, None, Some('}'), false), Some('}'));
+        assert_eq!(closing('(', opts, None, Some('x'), false), None);
+        assert_eq!(closing('"', opts, Some(' '), None, false), Some('"'));
+        assert_eq!(closing('"', opts, Some(' '), None, true), None);
+        assert_eq!(closing('\'', opts, Some('('), None, false), Some('\''));
+        assert_eq!(closing('\'', opts, Some('&'), None, false), None);
+        assert_eq!(closing('\'', opts, Some('<'), Some('>'), false), None);
+        assert_eq!(closing('\'', opts, Some('n'), None, false), None);
+        assert_eq!(closing('x', opts, None, None, false), None);
+        assert!(is_pair('[', ']', opts));
+        assert!(is_pair('"', '"', opts));
+        assert!(!is_pair('(', ']', opts));
+    }
+}
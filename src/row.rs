@@ -1,15 +1,30 @@
 use crate::highlighting;
+use crate::style::{Span, Style};
 use crate::HighlightingOptions;
 use crate::SearchDirection;
+use crate::Theme;
 use std::cmp;
-use termion::color;
+use termion::{clear, style};
 use unicode_segmentation::UnicodeSegmentation;
 
+/// Number of characters looked at for the end of an escape sequence, enough
+/// for `\u{10FFFF}`
+const MAX_ESCAPE_LEN: usize = 10;
+
+/// The pairs of brackets matched with each other
+pub const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
 #[derive(Default)]
 pub struct Row {
     string: String,
-    pub is_highlighted: bool,
     highlighting: Vec<highlighting::Type>,
+    /// The lexer states at the start and at the end of the row for which
+    /// `highlighting` was computed, or `None` if the row changed since
+    highlighted_states: Option<(highlighting::State, highlighting::State)>,
+    /// The search word whose matches are in `matches`
+    highlighted_word: Option<String>,
+    /// The graphemes matching the search word, as `(start, end)` ranges
+    matches: Vec<(usize, usize)>,
     len: usize,
 }
 
@@ -18,19 +33,27 @@ impl From<&str> for Row {
         Self {
             string: String::from(slice),
             highlighting: Vec::new(),
-            is_highlighted: false,
+            highlighted_states: None,
+            highlighted_word: None,
+            matches: Vec::new(),
             len: slice.graphemes(true).count(),
         }
     }
 }
 
 impl Row {
+    /// Renders the graphemes between `start` and `end`. Each one gets the
+    /// `base` style, with the style the `theme` gives to its highlighting
+    /// drawn over it, then the search match style and the `overlays` in
+    /// order. Escape sequences are only emitted where the style changes,
+    /// and a `base` background runs to the end of the screen line.
     #[must_use]
-    pub fn render(&self, start: usize, end: usize) -> String {
+    pub fn render(&self, start: usize, end: usize, theme: &Theme, base: Style, overlays: &[Span]) -> String {
         let end = cmp::min(end, self.string.len());
         let start = cmp::min(start, end);
         let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
+        let mut current_style = Style::default();
+        let match_style = theme.syntax(highlighting::Type::Match);
         #[allow(clippy::integer_arithmetic)]
         #[allow(clippy::string_slice)]
         for (index, grapheme) in self.string[..]
@@ -44,12 +67,17 @@ impl Row {
                     .highlighting
                     .get(index)
                     .unwrap_or(&highlighting::Type::None);
+                let mut grapheme_style = base.layer(theme.syntax(*highlighting_type));
+                if self.matches.iter().any(|&(match_start, match_end)| match_start <= index && index < match_end) {
+                    grapheme_style = grapheme_style.layer(match_style);
+                }
+                for overlay in overlays.iter().filter(|overlay| overlay.contains(index)) {
+                    grapheme_style = grapheme_style.layer(overlay.style);
+                }
 
-                if highlighting_type != current_highlighting {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&*start_highlight);
+                if grapheme_style != current_style {
+                    result.push_str(&grapheme_style.escape_from(&current_style));
+                    current_style = grapheme_style;
                 }
 
                 if c == '\t' {
@@ -59,7 +87,11 @@ impl Row {
                 }
             }
         }
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
+        if base.bg.is_some() {
+            result.push_str(&base.escape_from(&current_style));
+            result.push_str(&format!("{}", clear::UntilNewline));
+        }
+        let end_highlight = format!("{}", style::Reset);
         #[allow(clippy::string_slice)]
         result.push_str(&*end_highlight);
         result
@@ -77,9 +109,21 @@ impl Row {
         self.len == 0
     }
 
+    /// Drops the cached highlighting, so that the row is highlighted again
+    pub fn invalidate_highlighting(&mut self) {
+        self.highlighted_states = None;
+    }
+
+    /// Returns the lexer state at the end of the row, if it is highlighted
+    #[must_use]
+    pub fn end_state(&self) -> Option<highlighting::State> {
+        self.highlighted_states.map(|(_, end)| end)
+    }
+
     /// Inserts a new character into the selected row
     #[allow(clippy::string_slice)]
     pub fn insert(&mut self, at: usize, c: char) {
+        self.invalidate_highlighting();
         if at >= self.len() {
             self.string.push(c);
             self.len = self.len.saturating_add(1);
@@ -105,6 +149,7 @@ impl Row {
         if at >= self.len() {
             return;
         }
+        self.invalidate_highlighting();
         let mut result: String = String::new();
         let mut length: usize = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
@@ -117,8 +162,87 @@ impl Row {
         self.string = result;
     }
 
+    /// Deletes the graphemes from `start` up to, but not including, `end`
+    #[allow(clippy::string_slice)]
+    pub fn delete_range(&mut self, start: usize, end: usize) {
+        if start >= end || start >= self.len() {
+            return;
+        }
+        self.invalidate_highlighting();
+        let mut result: String = String::new();
+        let mut length: usize = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if index < start || index >= end {
+                length = length.saturating_add(1);
+                result.push_str(grapheme);
+            }
+        }
+        self.len = length;
+        self.string = result;
+    }
+
+    /// Returns the words of the row, split with `is_separator`, along with
+    /// the index of their first grapheme
+    #[must_use]
+    pub fn words(&self) -> Vec<(usize, String)> {
+        let mut words = Vec::new();
+        let mut word: Option<(usize, String)> = None;
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            if grapheme.chars().next().map_or(true, is_separator) {
+                words.extend(word.take());
+            } else {
+                word.get_or_insert_with(|| (index, String::new())).1.push_str(grapheme);
+            }
+        }
+        words.extend(word);
+        words
+    }
+
+    /// Returns the graphemes from `start` up to, but not including, `end`
+    #[must_use]
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        self.string
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect()
+    }
+
+    /// Inserts `text` at a given position
+    pub fn insert_str(&mut self, at: usize, text: &str) {
+        let tail = self.split(at);
+        self.append(&Self::from(text));
+        self.append(&tail);
+    }
+
+    /// Returns the number of whitespace graphemes the row starts with
+    #[must_use]
+    pub fn indentation(&self) -> usize {
+        self.string
+            .graphemes(true)
+            .take_while(|grapheme| grapheme.chars().all(char::is_whitespace))
+            .count()
+    }
+
+    /// Checks if the row only contains whitespace
+    #[must_use]
+    pub fn is_blank(&self) -> bool {
+        self.indentation() == self.len
+    }
+
+    /// Checks if `text` appears in the row at the grapheme index `at`
+    #[must_use]
+    pub fn contains_at(&self, at: usize, text: &str) -> bool {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .and_then(|(byte_index, _)| self.string.get(byte_index..))
+            .map_or(false, |rest| rest.starts_with(text))
+    }
+
     /// Appends a row to a given `Row`
     pub fn append(&mut self, new: &Self) {
+        self.invalidate_highlighting();
         self.string = format!("{}{}", self.string, new.string);
         self.len = self.len.saturating_add(new.len);
     }
@@ -143,21 +267,94 @@ impl Row {
 
         self.string = row;
         self.len = length;
-        self.is_highlighted = false;
+        self.invalidate_highlighting();
         Self {
             string: splitted_row,
             len: splitted_length,
-            is_highlighted: false,
+            highlighted_states: None,
+            highlighted_word: None,
+            matches: Vec::new(),
             highlighting: Vec::new(),
         }
     }
 
+    /// Returns the brackets of the row with their grapheme index, leaving
+    /// out those inside strings and comments. The row must be highlighted.
+    #[must_use]
+    pub fn brackets(&self) -> Vec<(usize, char)> {
+        self.string
+            .graphemes(true)
+            .enumerate()
+            .filter_map(|(index, grapheme)| {
+                let c = grapheme.chars().next()?;
+                let in_code = self
+                    .highlighting
+                    .get(index)
+                    .map_or(true, |hl_type| !hl_type.is_string_or_comment());
+                (in_code && BRACKETS.iter().any(|&(open, close)| c == open || c == close)).then_some((index, c))
+            })
+            .collect()
+    }
+
+    /// Returns the first character of the grapheme at `index`
+    #[must_use]
+    pub fn char_at(&self, index: usize) -> Option<char> {
+        self.string
+            .graphemes(true)
+            .nth(index)
+            .and_then(|grapheme| grapheme.chars().next())
+    }
+
+    /// Returns the highlighting type of the grapheme at `index`, as of the
+    /// last time the row was highlighted
+    #[must_use]
+    pub fn highlighting_at(&self, index: usize) -> Option<highlighting::Type> {
+        self.highlighting.get(index).copied()
+    }
+
+    /// Returns the search word the row was last highlighted with
+    #[must_use]
+    pub fn highlighted_word(&self) -> &Option<String> {
+        &self.highlighted_word
+    }
+
+    /// Returns the contents of the row
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
     /// Returns a byte slice of the Row's `String`'s contents
     #[must_use]
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
 
+    /// Returns the number of UTF-16 code units before the grapheme at
+    /// `index`, which is how language servers count columns
+    #[must_use]
+    pub fn utf16_offset(&self, index: usize) -> usize {
+        self.string
+            .graphemes(true)
+            .take(index)
+            .map(|grapheme| grapheme.encode_utf16().count())
+            .sum()
+    }
+
+    /// Returns the index of the grapheme starting at the UTF-16 `offset`,
+    /// or of the next one if `offset` falls inside a grapheme
+    #[must_use]
+    pub fn index_at_utf16(&self, offset: usize) -> usize {
+        let mut units: usize = 0;
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            if units >= offset {
+                return index;
+            }
+            units = units.saturating_add(grapheme.encode_utf16().count());
+        }
+        self.len
+    }
+
     /// Returns the elements that correponds to the search query and direction
     /// passed
     #[must_use]
@@ -206,6 +403,7 @@ impl Row {
 
     /// Highlight the matches found when user searchs for an element
     fn highlight_match(&mut self, word: &Option<String>) {
+        self.matches.clear();
         if let Some(ref word) = *word {
             if word.is_empty() {
                 return;
@@ -213,11 +411,7 @@ impl Row {
             let mut index = 0;
             while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
                 if let Some(next_index) = search_match.checked_add(word.get(..).expect("Failed while trying to get slice of `word`").graphemes(true).count()) {
-                    for i in search_match..next_index {
-                       if let Some(element) = self.highlighting.get_mut(i) {
-                           *element = highlighting::Type::Match;
-                       }
-                    }
+                    self.matches.push((search_match, next_index));
                     index = next_index;
                 } else {
                     break;
@@ -281,178 +475,460 @@ impl Row {
         self.highlight_keywords(index, chars, opts.secondary_keywords(), highlighting::Type::SecondaryKeywords)
     }
 
-    /// Returns a boolean and does the logic to highlight a `char`
+    /// Highlights the characters from `index` up to, but not including,
+    /// `until` with a given type
+    fn highlight_until(&mut self, index: &mut usize, until: usize, hl_type: highlighting::Type) {
+        while *index < until {
+            self.highlighting.push(hl_type);
+            *index = index.saturating_add(1);
+        }
+    }
+
+    /// Returns a boolean and does the logic to highlight a `char`, or a
+    /// lifetime if the quote is not closed right after an identifier
     fn highlight_char(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
-        if opts.characters() && c == '\'' {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                let closing_index = if *next_char == '\\' {
-                    index.saturating_add(3)
-                } else {
-                    index.saturating_add(2)
+        if !opts.characters() || c != '\'' {
+            return false;
+        }
+        let next_index = index.saturating_add(1);
+        match chars.get(next_index) {
+            Some('\\') => {
+                let Some(closing_index) = (next_index.saturating_add(2)..chars.len())
+                    .take(MAX_ESCAPE_LEN)
+                    .find(|&i| chars.get(i) == Some(&'\''))
+                else {
+                    return false;
                 };
-                if let Some(closing_char) = chars.get(closing_index) {
-                    if *closing_char == '\'' {
-                        for _ in 0..=closing_index.saturating_sub(*index) {
-                            self.highlighting.push(highlighting::Type::Character);
-                            *index = index.saturating_add(1);
-                        }
-                        return true;
-                    }
-                }
+                self.highlight_until(index, next_index, highlighting::Type::Character);
+                self.highlight_until(index, closing_index, highlighting::Type::Escape);
+                self.highlight_until(index, closing_index.saturating_add(1), highlighting::Type::Character);
+                true
             }
-
+            Some(_) if chars.get(next_index.saturating_add(1)) == Some(&'\'') => {
+                self.highlight_until(index, next_index.saturating_add(2), highlighting::Type::Character);
+                true
+            }
+            Some(&next_char) if opts.lifetimes() && is_identifier_start(next_char) => {
+                let end = identifier_end(chars, next_index);
+                self.highlight_until(index, end, highlighting::Type::Lifetime);
+                true
+            }
+            _ => false,
         }
-        false
     }
 
-    /// Returns a boolean and does the logic to highlight a comment
-    fn highlight_comment(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '/' {
-                    for _ in *index..chars.len() {
-                        self.highlighting.push(highlighting::Type::Comment);
-                        *index = index.saturating_add(1);
-                    }
-                    return true;
+    /// Returns a boolean and does the logic to highlight a comment that
+    /// runs until the end of the row
+    fn highlight_comment(&mut self, index: &mut usize, opts: &HighlightingOptions, chars: &[char]) -> bool {
+        let hl_type = if is_doc_comment(chars, *index, opts.doc_comments(), None) {
+            highlighting::Type::DocComment
+        } else if opts
+            .line_comments()
+            .iter()
+            .any(|token| starts_with_at(chars, *index, token))
+        {
+            highlighting::Type::Comment
+        } else {
+            return false;
+        };
+        self.highlight_until(index, chars.len(), hl_type);
+        true
+    }
+
+    /// Returns a boolean and does the logic to highlight an attribute, up to
+    /// its matching closing bracket or the end of the row
+    fn highlight_attribute(&mut self, index: &mut usize, opts: &HighlightingOptions, chars: &[char]) -> bool {
+        let Some(token) = opts
+            .attributes()
+            .iter()
+            .find(|token| starts_with_at(chars, *index, token))
+        else {
+            return false;
+        };
+        let mut depth: usize = 1;
+        let mut end = index.saturating_add(token.chars().count());
+        while let Some(&c) = chars.get(end) {
+            end = end.saturating_add(1);
+            if c == '[' {
+                depth = depth.saturating_add(1);
+            } else if c == ']' {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    break;
                 }
-            };
+            }
         }
-        false
+        self.highlight_until(index, end, highlighting::Type::Attribute);
+        true
     }
 
-    fn highlight_multiline_comment(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '*' {
-                    let indexed = self.string.get(index.saturating_add(2)..2).expect("Failed while trying to indexing string.").find("*/");
+    /// Returns a boolean and does the logic to highlight a `name!` macro
+    /// invocation, leaving `name != value` alone
+    fn highlight_macro(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
+        if !opts.macros() || !is_identifier_start(c) || !is_token_start(chars, *index) {
+            return false;
+        }
+        let end = identifier_end(chars, *index);
+        if chars.get(end) != Some(&'!') || chars.get(end.saturating_add(1)) == Some(&'=') {
+            return false;
+        }
+        self.highlight_until(index, end.saturating_add(1), highlighting::Type::Macro);
+        true
+    }
 
-                    let closing_index = if let Some(closing_index) = indexed {
-                        index.saturating_add(closing_index).saturating_add(4)
-                    } else {
-                        chars.len()
-                    };
+    /// Opens a string if `index` is at a string delimiter, or at a raw or
+    /// prefixed one such as `r#"` or `b"`, and returns the state after it
+    fn highlight_string_start(&mut self, index: &mut usize, opts: &HighlightingOptions, chars: &[char]) -> Option<highlighting::State> {
+        if !is_token_start(chars, *index) {
+            return opts
+                .string_delimiters()
+                .iter()
+                .find(|&&delimiter| chars.get(*index) == Some(&delimiter))
+                .map(|&delimiter| {
+                    self.highlight_until(index, index.saturating_add(1), highlighting::Type::String);
+                    highlighting::State::String(delimiter)
+                });
+        }
+        if opts.raw_strings() {
+            let mut quote = *index;
+            if chars.get(quote) == Some(&'b') {
+                quote = quote.saturating_add(1);
+            }
+            if chars.get(quote) == Some(&'r') {
+                let hashes_start = quote.saturating_add(1);
+                let quote = (hashes_start..chars.len())
+                    .find(|&i| chars.get(i) != Some(&'#'))
+                    .unwrap_or(chars.len());
+                if chars.get(quote) == Some(&'"') {
+                    let hashes = u8::try_from(quote.saturating_sub(hashes_start)).unwrap_or(u8::MAX);
+                    self.highlight_until(index, quote.saturating_add(1), highlighting::Type::String);
+                    return Some(highlighting::State::RawString(hashes));
+                }
+            }
+        }
+        let prefix_len = opts
+            .string_prefixes()
+            .iter()
+            .filter(|prefix| starts_with_at(chars, *index, prefix))
+            .map(|prefix| prefix.chars().count())
+            .filter(|&len| {
+                chars
+                    .get(index.saturating_add(len))
+                    .map_or(false, |c| opts.string_delimiters().contains(c))
+            })
+            .max()
+            .unwrap_or(0);
+        let quote = index.saturating_add(prefix_len);
+        let &delimiter = chars.get(quote).filter(|c| opts.string_delimiters().contains(c))?;
+        self.highlight_until(index, quote.saturating_add(1), highlighting::Type::String);
+        Some(highlighting::State::String(delimiter))
+    }
 
-                    for _ in *index..closing_index {
-                        self.highlighting.push(highlighting::Type::MultilineComment);
-                        *index = index.saturating_add(1);
-                    }
-                    return true;
+    /// Highlights the rest of a block comment up to and including the end
+    /// token closing its outermost level, and returns the state after it
+    fn continue_block_comment(&mut self, index: &mut usize, opts: &HighlightingOptions, mut depth: u8, doc: bool, chars: &[char]) -> highlighting::State {
+        let Some((start, end)) = opts.block_comment() else {
+            return highlighting::State::Normal;
+        };
+        let hl_type = if doc {
+            highlighting::Type::DocComment
+        } else {
+            highlighting::Type::MultilineComment
+        };
+        while *index < chars.len() {
+            if starts_with_at(chars, *index, end) {
+                self.highlight_until(index, index.saturating_add(end.chars().count()), hl_type);
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return highlighting::State::Normal;
                 }
-            };
+            } else if opts.nested_comments() && starts_with_at(chars, *index, start) {
+                self.highlight_until(index, index.saturating_add(start.chars().count()), hl_type);
+                depth = depth.saturating_add(1);
+            } else {
+                self.highlight_until(index, index.saturating_add(1), hl_type);
+            }
         }
-        false
+        highlighting::State::BlockComment { depth, doc }
+    }
+
+    /// Highlights the rest of a string up to and including its closing
+    /// `delimiter`, and returns the state after it. Escape sequences are
+    /// highlighted on their own and never close the string.
+    fn continue_string(&mut self, index: &mut usize, opts: &HighlightingOptions, delimiter: char, chars: &[char]) -> highlighting::State {
+        while let Some(&c) = chars.get(*index) {
+            if Some(c) == opts.string_escape() {
+                let end = escape_end(chars, *index);
+                self.highlight_until(index, end, highlighting::Type::Escape);
+                continue;
+            }
+            self.highlight_until(index, index.saturating_add(1), highlighting::Type::String);
+            if c == delimiter {
+                return highlighting::State::Normal;
+            }
+        }
+        highlighting::State::String(delimiter)
+    }
+
+    /// Highlights the rest of a raw string up to and including its closing
+    /// `"` followed by `hashes` hashes, and returns the state after it
+    fn continue_raw_string(&mut self, index: &mut usize, hashes: u8, chars: &[char]) -> highlighting::State {
+        let closing = format!("\"{}", "#".repeat(usize::from(hashes)));
+        let (closing_index, state) = find_at(chars, *index, &closing).map_or(
+            (chars.len(), highlighting::State::RawString(hashes)),
+            |found| (found.saturating_add(closing.len()), highlighting::State::Normal),
+        );
+        self.highlight_until(index, closing_index, highlighting::Type::String);
+        state
     }
 
-    /// Returns a boolean and does the logic to highlight a `string`
-    fn highlight_string(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
-        if opts.strings() && c == '"' {
-            loop {
-                self.highlighting.push(highlighting::Type::String);
-                *index = index.saturating_add(1);
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char == '"' {
-                        break;
-                    }
+    /// Highlights the token starting at `index` outside of comments and
+    /// strings, and returns the state after it
+    fn highlight_token(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> highlighting::State {
+        if let Some((start, end)) = opts.block_comment() {
+            if starts_with_at(chars, *index, start) {
+                let doc = is_doc_comment(chars, *index, opts.block_doc_comments(), Some(end));
+                let hl_type = if doc {
+                    highlighting::Type::DocComment
                 } else {
-                    break;
-                }
+                    highlighting::Type::MultilineComment
+                };
+                self.highlight_until(index, index.saturating_add(start.chars().count()), hl_type);
+                return highlighting::State::BlockComment { depth: 1, doc };
             }
-            self.highlighting.push(highlighting::Type::String);
+        }
+
+        if self.highlight_comment(index, opts, chars) || self.highlight_attribute(index, opts, chars) {
+            return highlighting::State::Normal;
+        }
+
+        if let Some(state) = self.highlight_string_start(index, opts, chars) {
+            return state;
+        }
+
+        if self.highlight_char(index, opts, c, chars) || self.highlight_macro(index, opts, c, chars) || self.highlight_primary_keywords(index, opts, chars) || self.highlight_secondary_keywords(index, opts, chars) {
+            return highlighting::State::Normal;
+        }
+
+        if !self.highlight_number(index, opts, c, chars) {
+            self.highlighting.push(highlighting::Type::None);
             *index = index.saturating_add(1);
-            return true;
         }
-        false
+        highlighting::State::Normal
     }
 
-    /// Returns a boolean and does the logic to highlight a number
+    /// Returns a boolean and does the logic to highlight a number, with its
+    /// prefix, digit separators, fraction, exponent and type suffix
     fn highlight_number(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
-        if opts.numbers() && c.is_ascii_digit() {
-            if *index > 0 {
-                let prev_char = chars.get(index.saturating_sub(1)).expect("Failed trying to index `chars` at `highlight_number()`");
-                if !is_separator(*prev_char) {
-                    return false;
-                }
+        if !opts.numbers() || !c.is_ascii_digit() || !is_token_start(chars, *index) {
+            return false;
+        }
+        let separator = opts.number_separator();
+        let digits_end = |from: usize, is_digit: fn(&char) -> bool| {
+            (from..chars.len())
+                .find(|&i| chars.get(i).map_or(true, |c| !is_digit(c) && Some(*c) != separator))
+                .unwrap_or(chars.len())
+        };
+        let mut end;
+        if let Some(prefix) = opts
+            .number_prefixes()
+            .iter()
+            .find(|prefix| starts_with_at(chars, *index, prefix))
+        {
+            end = digits_end(index.saturating_add(prefix.chars().count()), char::is_ascii_hexdigit);
+        } else {
+            end = digits_end(*index, char::is_ascii_digit);
+            let fraction = end.saturating_add(1);
+            if chars.get(end) == Some(&'.') && chars.get(fraction).map_or(false, char::is_ascii_digit) {
+                end = digits_end(fraction, char::is_ascii_digit);
             }
-            loop {
-                self.highlighting.push(highlighting::Type::Number);
-                *index = index.saturating_add(1);
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char != '.' && !next_char.is_ascii_digit() {
-                        break;
-                    }
-                } else {
-                    break;
+            if opts.number_exponents() && matches!(chars.get(end), Some('e' | 'E')) {
+                let mut exponent = end.saturating_add(1);
+                if matches!(chars.get(exponent), Some('+' | '-')) {
+                    exponent = exponent.saturating_add(1);
+                }
+                if chars.get(exponent).map_or(false, char::is_ascii_digit) {
+                    end = digits_end(exponent, char::is_ascii_digit);
                 }
             }
-            return true;
         }
-        false
+        if let Some(suffix) = opts.number_suffixes().iter().find(|suffix| {
+            starts_with_at(chars, end, suffix)
+                && !chars
+                    .get(end.saturating_add(suffix.chars().count()))
+                    .map_or(false, |&c| is_identifier_char(c))
+        }) {
+            end = end.saturating_add(suffix.chars().count());
+        }
+        self.highlight_until(index, end, highlighting::Type::Number);
+        true
     }
 
-    /// Check if any of the `HighlightingOptions` applies and if not,
-    /// pushes to the `highlighting` vec `None`
-    pub fn highlight(&mut self, opts: &HighlightingOptions, word: &Option<String>, start_with_comment: bool) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment && self.string.get(self.string.len().saturating_sub(2)..).expect("Failed while indexing string.") == "*/" {
-                    return true;
-                }
+    /// Highlights the row as if it started in `start_state`, along with the
+    /// matches of `word`, and returns the lexer state at the end of the row.
+    /// The highlighting is kept until the row changes, so this only lexes
+    /// the row again for a different start state or search word.
+    pub fn highlight(&mut self, opts: &HighlightingOptions, word: &Option<String>, start_state: highlighting::State) -> highlighting::State {
+        if let Some((cached_start, cached_end)) = self.highlighted_states {
+            if cached_start == start_state && self.highlighted_word == *word {
+                return cached_end;
             }
-            return false;
         }
-        self.highlighting = Vec::new();
+        let chars: Vec<char> = self.string.chars().collect();
+        self.highlighting = Vec::with_capacity(chars.len());
         let mut index = 0;
-        let mut in_ml_comment = start_with_comment;
-        if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index.saturating_add(2)
-            } else {
-                chars.len()
+        let mut state = start_state;
+        if opts.diff_lines() {
+            let (line_type, next_state) = diff_line(&self.string, state);
+            self.highlighting = vec![line_type; chars.len()];
+            index = chars.len();
+            state = next_state;
+        }
+        while let Some(&c) = chars.get(index) {
+            state = match state {
+                highlighting::State::Normal | highlighting::State::DiffHunk { .. } => {
+                    self.highlight_token(&mut index, opts, c, &chars)
+                }
+                highlighting::State::BlockComment { depth, doc } => self.continue_block_comment(&mut index, opts, depth, doc, &chars),
+                highlighting::State::String(delimiter) => self.continue_string(&mut index, opts, delimiter, &chars),
+                highlighting::State::RawString(hashes) => self.continue_raw_string(&mut index, hashes, &chars),
             };
-            for _ in 0..closing_index {
-                self.highlighting.push(highlighting::Type::MultilineComment);
-            }
-            index = closing_index;
         }
-        while let Some(c) = chars.get(index) {
-            if self.highlight_multiline_comment(&mut index, opts, *c, &chars) {
-                in_ml_comment = true;
-                continue;
-            }
-            in_ml_comment = false;
-
-            if self.highlight_char(&mut index, opts, *c, &chars) || self.highlight_comment(&mut index, opts, *c, &chars) || self.highlight_primary_keywords(&mut index, opts, &chars) || self.highlight_secondary_keywords(&mut index, opts, &chars) || self.highlight_string(&mut index, opts, *c, &chars) || self.highlight_number(&mut index, opts, *c, &chars) {
-                continue;
-            }
-
-            // We reserve capacity in the highlighting array for at least one more
-            // element, to check that the `.push()` is safe as we are using `usize`
-            self.highlighting.reserve(1);
-            self.highlighting.push(highlighting::Type::None);
-
-            index = index.saturating_add(1);
+        if matches!(state, highlighting::State::String(_)) && !opts.multiline_strings() {
+            state = highlighting::State::Normal;
         }
         self.highlight_match(word);
-        let comment_range = self.string.get(self.string.len().saturating_sub(2)..).expect("Failed while trying to index string");
-        
-        if in_ml_comment && comment_range != "*/" {
-            return true;
-        }
-        self.is_highlighted = true;
-        false
+        self.highlighted_states = Some((start_state, state));
+        self.highlighted_word = word.clone();
+        state
     }
 
 }
 
-fn is_separator(c: char) -> bool {
-    c.is_ascii_punctuation() || c.is_ascii_whitespace()
+/// Returns the type of a line of a unified diff starting in `state`, and
+/// the state of the next line. Lines starting with `---` and `+++` are only
+/// file headers outside of the hunks, which end after the numbers of lines
+/// given by their `@@` header.
+fn diff_line(line: &str, state: highlighting::State) -> (highlighting::Type, highlighting::State) {
+    const HEADERS: [&str; 5] = ["+++", "---", "@@", "diff ", "index "];
+    if let highlighting::State::DiffHunk { old, new } = state {
+        let (line_type, old, new) = if line.starts_with('+') {
+            (highlighting::Type::DiffAdded, old, new.saturating_sub(1))
+        } else if line.starts_with('-') {
+            (highlighting::Type::DiffRemoved, old.saturating_sub(1), new)
+        } else if line.starts_with('\\') {
+            // "\ No newline at end of file"
+            (highlighting::Type::None, old, new)
+        } else {
+            (highlighting::Type::None, old.saturating_sub(1), new.saturating_sub(1))
+        };
+        let state = if old == 0 && new == 0 {
+            highlighting::State::Normal
+        } else {
+            highlighting::State::DiffHunk { old, new }
+        };
+        return (line_type, state);
+    }
+    if HEADERS.iter().any(|header| line.starts_with(header)) {
+        let state = hunk_lengths(line).map_or(highlighting::State::Normal, |(old, new)| {
+            highlighting::State::DiffHunk { old, new }
+        });
+        (highlighting::Type::DiffHeader, state)
+    } else if line.starts_with('+') {
+        (highlighting::Type::DiffAdded, state)
+    } else if line.starts_with('-') {
+        (highlighting::Type::DiffRemoved, state)
+    } else {
+        (highlighting::Type::None, state)
+    }
+}
+
+/// Returns the numbers of lines of the old and the new text in a hunk with
+/// the header `line`, like `@@ -1,5 +1,6 @@`, where a missing number is 1
+fn hunk_lengths(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.strip_prefix("@@ ")?.split_whitespace();
+    let length = |range: Option<&str>, sign: char| -> Option<usize> {
+        let range = range?.strip_prefix(sign)?;
+        range.split_once(',').map_or(Some(1), |(_, length)| length.parse().ok())
+    };
+    let old = length(ranges.next(), '-')?;
+    let new = length(ranges.next(), '+')?;
+    (old > 0 || new > 0).then_some((old, new))
+}
+
+/// Checks if `c` separates words
+#[must_use]
+pub fn is_separator(c: char) -> bool {
+    c != '_' && (c.is_ascii_punctuation() || c.is_ascii_whitespace())
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Checks if a token starting at `index` is not the middle of an identifier
+fn is_token_start(chars: &[char], index: usize) -> bool {
+    index
+        .checked_sub(1)
+        .and_then(|previous| chars.get(previous))
+        .map_or(true, |&c| !is_identifier_char(c))
+}
+
+/// Returns the position right after the identifier starting at `index`
+fn identifier_end(chars: &[char], index: usize) -> usize {
+    (index..chars.len())
+        .find(|&i| chars.get(i).map_or(true, |&c| !is_identifier_char(c)))
+        .unwrap_or(chars.len())
+}
+
+/// Returns the position right after the escape sequence starting at
+/// `index`, such as `\n`, `\x7f` or `\u{1F600}`
+fn escape_end(chars: &[char], index: usize) -> usize {
+    let len = match chars.get(index.saturating_add(1)) {
+        Some('x') => 4,
+        Some('u') if chars.get(index.saturating_add(2)) == Some(&'{') => (index..chars.len())
+            .take(MAX_ESCAPE_LEN)
+            .find(|&i| chars.get(i) == Some(&'}'))
+            .map_or(2, |closing| closing.saturating_sub(index).saturating_add(1)),
+        _ => 2,
+    };
+    cmp::min(index.saturating_add(len), chars.len())
+}
+
+/// Checks if one of the doc comment `tokens` starts at `index`. Like in
+/// Rust, `////` and `/***` are plain comments, and so is `/**/` when the
+/// comment `end` token closes it right away.
+fn is_doc_comment(chars: &[char], index: usize, tokens: &[String], end: Option<&str>) -> bool {
+    tokens.iter().any(|token| {
+        let len = token.chars().count();
+        starts_with_at(chars, index, token)
+            && token.chars().last() != chars.get(index.saturating_add(len)).copied()
+            && end.map_or(true, |end| !starts_with_at(chars, index.saturating_add(len).saturating_sub(1), end))
+    })
+}
+
+/// Checks if `token` appears in `chars` at position `index`
+fn starts_with_at(chars: &[char], index: usize, token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| chars.get(index.saturating_add(offset)) == Some(&c))
+}
+
+/// Returns the position of the first `token` in `chars` at or after `from`
+fn find_at(chars: &[char], from: usize, token: &str) -> Option<usize> {
+    (from..chars.len()).find(|&index| starts_with_at(chars, index, token))
 }
 
 #[cfg(test)]
 mod test_super {
     use super::*;
+    use crate::FileType;
 
     #[test]
     fn test_highlight_find() {
@@ -469,20 +945,9 @@ mod test_super {
             highlighting::Type::None,
         ];
         row.highlight_match(&Some("t".to_owned()));
-        assert_eq!(
-            vec![
-                highlighting::Type::Number,
-                highlighting::Type::Match,
-                highlighting::Type::None,
-                highlighting::Type::None,
-                highlighting::Type::Match,
-                highlighting::Type::Match,
-                highlighting::Type::None,
-                highlighting::Type::None,
-                highlighting::Type::Match
-            ],
-            row.highlighting
-        );
+        assert_eq!(row.matches, vec![(1, 2), (4, 5), (5, 6), (8, 9)]);
+        assert_eq!(row.highlighting.first(), Some(&highlighting::Type::Number));
+        assert!(row.highlighting.iter().skip(1).all(|&hl_type| hl_type == highlighting::Type::None));
     }
 
     #[test]
@@ -493,4 +958,137 @@ mod test_super {
         assert_eq!(row.find("t", 5, SearchDirection::Forward), Some(5));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_utf16_offsets() {
+        let row = Row::from("a\u{1f600}e\u{301}b");
+        assert_eq!(row.utf16_offset(2), 3);
+        assert_eq!(row.utf16_offset(3), 5);
+        assert_eq!(row.index_at_utf16(3), 2);
+        assert_eq!(row.index_at_utf16(2), 2);
+        assert_eq!(row.index_at_utf16(9), 4);
+    }
+
+    #[test]
+    fn test_highlight_configured_comments() {
+        let shell = FileType::parse(
+            "[language]\nname = Shell\nline_comment = #\nstring_delimiters = \" '\n",
+        )
+        .expect("valid definition");
+        let opts = shell.first().expect("one language").highlighting_options();
+        let mut row = Row::from("x 'a#' # note");
+        row.highlight(opts, &None, highlighting::State::Normal);
+        assert_eq!(row.highlighting.get(2), Some(&highlighting::Type::String));
+        assert_eq!(row.highlighting.get(4), Some(&highlighting::Type::String));
+        assert_eq!(row.highlighting.get(7), Some(&highlighting::Type::Comment));
+
+        let markdown = FileType::parse("[language]\nname = Markdown\nblock_comment = <!-- -->\n")
+            .expect("valid definition");
+        let opts = markdown.first().expect("one language").highlighting_options();
+        let mut row = Row::from("text <!-- open");
+        assert_eq!(
+            row.highlight(opts, &None, highlighting::State::Normal),
+            highlighting::State::BlockComment { depth: 1, doc: false }
+        );
+        assert_eq!(row.highlighting.get(5), Some(&highlighting::Type::MultilineComment));
+        let mut row = Row::from("still --> done");
+        assert_eq!(
+            row.highlight(opts, &None, highlighting::State::BlockComment { depth: 1, doc: false }),
+            highlighting::State::Normal
+        );
+        assert_eq!(row.highlighting.get(8), Some(&highlighting::Type::MultilineComment));
+        assert_eq!(row.highlighting.get(10), Some(&highlighting::Type::None));
+    }
+
+    #[test]
+    fn test_highlight_diff() {
+        let diff = FileType::from("fix.patch");
+        let lines = [
+            "--- a/src/main.rs",
+            "+++ b/src/main.rs",
+            "@@ -1,2 +1,2 @@",
+            " 42",
+            "--- a removed comment",
+            "+++i;",
+            "--- a/README.md",
+            "+++ b/README.md",
+            "@@ -1 +1 @@",
+            "-// gone",
+            "+let a = \"1\";",
+        ];
+        let mut state = highlighting::State::Normal;
+        let types: Vec<Option<highlighting::Type>> = lines
+            .iter()
+            .map(|&line| {
+                let mut row = Row::from(line);
+                state = row.highlight(diff.highlighting_options(), &None, state);
+                row.highlighting.first().copied()
+            })
+            .collect();
+        let (header, added, removed) = (
+            Some(highlighting::Type::DiffHeader),
+            Some(highlighting::Type::DiffAdded),
+            Some(highlighting::Type::DiffRemoved),
+        );
+        assert_eq!(
+            types,
+            [header, header, header, Some(highlighting::Type::None), removed, added, header, header, header, removed, added]
+        );
+        assert_eq!(state, highlighting::State::Normal);
+    }
+
+    fn rust_types(text: &str, start_state: highlighting::State) -> (Vec<highlighting::Type>, highlighting::State) {
+        let rust = FileType::by_name("rust").expect("Rust is built in");
+        let mut row = Row::from(text);
+        let state = row.highlight(rust.highlighting_options(), &None, start_state);
+        (row.highlighting, state)
+    }
+
+    #[test]
+    fn test_highlight_rust() {
+        use highlighting::Type::{
+            Attribute, Character, Comment, DocComment, Escape, Lifetime, Macro, MultilineComment, None, Number, String,
+        };
+        let normal = highlighting::State::Normal;
+
+        let (types, _) = rust_types("'a' '\\'' &'a", normal);
+        assert_eq!(&types[..3], &[Character; 3]);
+        assert_eq!(&types[4..10], &[Character, Escape, Escape, Character, None, None]);
+        assert_eq!(&types[10..], &[Lifetime; 2]);
+
+        let (types, state) = rust_types("\"a\\\"b\" r#\"x\"y\"#", normal);
+        assert_eq!(&types[..7], &[String, String, Escape, Escape, String, String, None]);
+        assert_eq!(&types[7..], &[String; 8]);
+        assert_eq!(state, normal);
+        let (_, state) = rust_types("br##\"open", normal);
+        assert_eq!(state, highlighting::State::RawString(2));
+        let (types, state) = rust_types("\"# \"##;", highlighting::State::RawString(2));
+        assert_eq!(&types[..6], &[String; 6]);
+        assert_eq!(state, normal);
+
+        let (types, state) = rust_types("/* a /* b */ c", normal);
+        assert_eq!(types, vec![MultilineComment; 14]);
+        assert_eq!(state, highlighting::State::BlockComment { depth: 1, doc: false });
+        let (types, _) = rust_types("/// doc", normal);
+        assert_eq!(types, vec![DocComment; 7]);
+        let (types, _) = rust_types("//// plain", normal);
+        assert_eq!(types, vec![Comment; 10]);
+        let (_, state) = rust_types("/** doc", normal);
+        assert_eq!(state, highlighting::State::BlockComment { depth: 1, doc: true });
+        let (_, state) = rust_types("/**/ x", normal);
+        assert_eq!(state, normal);
+
+        let (types, _) = rust_types("#[cfg(all(a, b[0]))] x", normal);
+        assert_eq!(&types[..20], &[Attribute; 20]);
+        assert_eq!(types.get(21), Some(&None));
+        let (types, _) = rust_types("vec![a != b]", normal);
+        assert_eq!(&types[..4], &[Macro; 4]);
+        assert_eq!(types.get(7), Some(&None));
+
+        let (types, _) = rust_types("0xFF_u8 1e-9 2.5f32 x1 1.max", normal);
+        assert_eq!(&types[..7], &[Number; 7]);
+        assert_eq!(&types[8..12], &[Number; 4]);
+        assert_eq!(&types[13..19], &[Number; 6]);
+        assert_eq!(&types[20..22], &[None; 2]);
+        assert_eq!(&types[23..25], &[Number, None]);
+    }
+}
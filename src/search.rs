@@ -0,0 +1,165 @@
+use crate::ignore::Ignore;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Number of bytes at the start of a file looked at for a NUL byte, which
+/// marks a binary file
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// A line of a project file containing the searched text
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Match {
+    /// The path of the file, relative to the project root
+    pub file: String,
+    /// The index of the line
+    pub line: usize,
+    /// The index of the grapheme where the first occurrence on the line
+    /// starts
+    pub column: usize,
+    pub text: String,
+}
+
+/// A search for a text in the files of a project, running in the
+/// background. Dropping it stops the search.
+pub struct Search {
+    matches: Receiver<Match>,
+    done: bool,
+}
+
+impl Search {
+    /// Starts searching `query` in the files under `root`, skipping the
+    /// ones ignored by `.gitignore` files and binary files
+    #[must_use]
+    pub fn spawn(root: PathBuf, query: String) -> Self {
+        let (sender, matches) = mpsc::channel();
+        thread::spawn(move || {
+            let mut ignore = Ignore::default();
+            search_dir(&root, "", &query, &mut ignore, &sender);
+        });
+        Self { matches, done: false }
+    }
+
+    /// Returns the matches found since the last call, without waiting for
+    /// more. They come file by file, in the order of the paths.
+    pub fn poll(&mut self) -> Vec<Match> {
+        let mut matches = Vec::new();
+        while !self.done {
+            match self.matches.try_recv() {
+                Ok(found) => matches.push(found),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.done = true,
+            }
+        }
+        matches
+    }
+
+    /// Checks if every file was searched and its matches polled
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// Searches the directory at `relative` under `root`, after adding its
+/// `.gitignore` to `ignore`. Returns `false` once nobody waits for the
+/// matches anymore.
+fn search_dir(root: &Path, relative: &str, query: &str, ignore: &mut Ignore, sender: &Sender<Match>) -> bool {
+    let dir = root.join(relative);
+    if let Ok(contents) = fs::read_to_string(dir.join(".gitignore")) {
+        ignore.add(relative, &contents);
+    }
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return true;
+    };
+    let mut entries: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((entry.file_name().into_string().ok()?, is_dir))
+        })
+        .collect();
+    entries.sort();
+    for (name, is_dir) in entries {
+        let path = if relative.is_empty() {
+            name.clone()
+        } else {
+            format!("{relative}/{name}")
+        };
+        if name == ".git" || ignore.is_ignored(&path, is_dir) {
+            continue;
+        }
+        let searched = if is_dir {
+            search_dir(root, &path, query, ignore, sender)
+        } else {
+            search_file(&root.join(&path), &path, query, sender)
+        };
+        if !searched {
+            return false;
+        }
+    }
+    true
+}
+
+/// Sends the lines of the file containing `query`. Binary files, and files
+/// that are not valid UTF-8, are skipped.
+fn search_file(path: &Path, relative: &str, query: &str, sender: &Sender<Match>) -> bool {
+    let Ok(bytes) = fs::read(path) else {
+        return true;
+    };
+    if bytes.iter().take(BINARY_CHECK_LEN).any(|&byte| byte == 0) {
+        return true;
+    }
+    let Ok(contents) = String::from_utf8(bytes) else {
+        return true;
+    };
+    for (line, text) in contents.lines().enumerate() {
+        if let Some(byte_index) = text.find(query) {
+            let found = Match {
+                file: relative.to_owned(),
+                line,
+                column: text.get(..byte_index).unwrap_or_default().graphemes(true).count(),
+                text: text.to_owned(),
+            };
+            if sender.send(found).is_err() {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_search() {
+        let root = std::env::temp_dir().join(format!("hammare-search-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).expect("created");
+        fs::create_dir_all(root.join("target")).expect("created");
+        fs::write(root.join(".gitignore"), "/target\n").expect("written");
+        fs::write(root.join("src/main.rs"), "fn main() {\n    let h\u{e9}llo = needle;\n}\n").expect("written");
+        fs::write(root.join("target/out.rs"), "needle\n").expect("written");
+        fs::write(root.join("data.bin"), b"needle\0\x01").expect("written");
+        fs::write(root.join("notes.txt"), "a needle\n").expect("written");
+
+        let mut search = Search::spawn(root.clone(), "needle".to_owned());
+        let mut matches = Vec::new();
+        let start = Instant::now();
+        while !search.is_done() {
+            matches.extend(search.poll());
+            assert!(start.elapsed() < Duration::from_secs(5), "the search did not finish");
+            thread::sleep(Duration::from_millis(10));
+        }
+        fs::remove_dir_all(&root).expect("removed");
+        let found: Vec<(&str, usize, usize)> = matches
+            .iter()
+            .map(|found| (found.file.as_str(), found.line, found.column))
+            .collect();
+        assert_eq!(found, [("notes.txt", 0, 2), ("src/main.rs", 1, 16)]);
+    }
+}
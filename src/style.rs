@@ -0,0 +1,459 @@
+use std::env;
+use std::io::{Error, ErrorKind};
+use termion::color;
+use termion::style;
+
+/// Levels of each component in the 6x6x6 color cube of the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The 16 basic ANSI colors, as rendered by xterm
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// How many colors the terminal is able to display
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    Monochrome,
+}
+
+impl ColorDepth {
+    /// Detects the color depth of the terminal from the `NO_COLOR`,
+    /// `COLORTERM` and `TERM` environment variables
+    #[must_use]
+    pub fn detect() -> Self {
+        if env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty()) {
+            return Self::Monochrome;
+        }
+        Self::detect_from(
+            env::var("COLORTERM").ok().as_deref(),
+            env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    fn detect_from(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            return Self::TrueColor;
+        }
+        match term {
+            None | Some("" | "dumb") => Self::Monochrome,
+            Some(term) if term.contains("truecolor") || term.contains("24bit") || term.contains("direct") => {
+                Self::TrueColor
+            }
+            Some(term) if term.contains("256color") => Self::Ansi256,
+            Some(_) => Self::Ansi16,
+        }
+    }
+
+    /// Returns the color depth with the given configuration name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "truecolor" | "24bit" => Some(Self::TrueColor),
+            "256" => Some(Self::Ansi256),
+            "16" => Some(Self::Ansi16),
+            "monochrome" | "none" => Some(Self::Monochrome),
+            _ => None,
+        }
+    }
+}
+
+/// A color as emitted to the terminal: either a truecolor value or an
+/// index in the 256 or 16 color palettes
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Color {
+    Rgb(u8, u8, u8),
+    Ansi256(u8),
+    Ansi16(u8),
+}
+
+impl Color {
+    /// Converts a truecolor value to the nearest color the terminal can
+    /// display, or `None` for monochrome terminals
+    #[must_use]
+    pub fn quantize(self, depth: ColorDepth) -> Option<Self> {
+        let Self::Rgb(r, g, b) = self else {
+            return Some(self);
+        };
+        match depth {
+            ColorDepth::TrueColor => Some(self),
+            ColorDepth::Ansi256 => Some(Self::Ansi256(nearest_256(r, g, b))),
+            ColorDepth::Ansi16 => Some(Self::Ansi16(nearest(&ANSI_16, (r, g, b)))),
+            ColorDepth::Monochrome => None,
+        }
+    }
+
+    fn fg_escape(self) -> String {
+        match self {
+            Self::Rgb(r, g, b) => format!("{}", color::Fg(color::Rgb(r, g, b))),
+            Self::Ansi256(index) => format!("{}", color::Fg(color::AnsiValue(index))),
+            Self::Ansi16(index) => ansi_16_escape(index, 30, 90),
+        }
+    }
+
+    fn bg_escape(self) -> String {
+        match self {
+            Self::Rgb(r, g, b) => format!("{}", color::Bg(color::Rgb(r, g, b))),
+            Self::Ansi256(index) => format!("{}", color::Bg(color::AnsiValue(index))),
+            Self::Ansi16(index) => ansi_16_escape(index, 40, 100),
+        }
+    }
+}
+
+/// A set of text attributes such as bold or underline
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub struct Attributes(u8);
+
+impl Attributes {
+    pub const BOLD: Self = Self(1);
+    pub const ITALIC: Self = Self(1 << 1);
+    pub const UNDERLINE: Self = Self(1 << 2);
+    pub const UNDERCURL: Self = Self(1 << 3);
+    pub const REVERSE: Self = Self(1 << 4);
+
+    /// Returns the attribute with the given name, as written in theme files
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bold" => Some(Self::BOLD),
+            "italic" => Some(Self::ITALIC),
+            "underline" => Some(Self::UNDERLINE),
+            "undercurl" => Some(Self::UNDERCURL),
+            "reverse" => Some(Self::REVERSE),
+            _ => None,
+        }
+    }
+
+    /// Checks if every attribute of `other` is set
+    #[must_use]
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the attributes set in either `self` or `other`
+    #[must_use]
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Checks if no attribute is set
+    #[must_use]
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the attributes set in `self` but not in `other`
+    fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Returns the escape sequence turning the attributes on
+    fn escape(self) -> String {
+        let mut result = String::new();
+        if self.contains(Self::BOLD) {
+            result.push_str(&format!("{}", style::Bold));
+        }
+        if self.contains(Self::ITALIC) {
+            result.push_str(&format!("{}", style::Italic));
+        }
+        if self.contains(Self::UNDERCURL) {
+            result.push_str("\x1b[4:3m");
+        } else if self.contains(Self::UNDERLINE) {
+            result.push_str(&format!("{}", style::Underline));
+        }
+        if self.contains(Self::REVERSE) {
+            result.push_str(&format!("{}", style::Invert));
+        }
+        result
+    }
+}
+
+/// How a piece of text looks: its colors and attributes. A color set to
+/// `None` leaves the terminal's default color.
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub attributes: Attributes,
+}
+
+impl Style {
+    /// Parses a style written as space separated `fg=#rrggbb`, `bg=#rrggbb`
+    /// and attribute names, e.g. `fg=#ff79c6 bold`
+    ///
+    /// # Errors
+    /// It will return `Err` if a color or attribute cannot be parsed
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let mut result = Self::default();
+        for token in value.split_whitespace() {
+            if let Some(hex) = token.strip_prefix("fg=") {
+                result.fg = Some(parse_color(hex)?);
+            } else if let Some(hex) = token.strip_prefix("bg=") {
+                result.bg = Some(parse_color(hex)?);
+            } else if let Some(attribute) = Attributes::from_name(token) {
+                result.attributes = result.attributes.union(attribute);
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unknown style attribute `{token}`"),
+                ));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Converts the colors to ones the terminal can display. Without
+    /// colors, a background is replaced by reverse video so that elements
+    /// like the status bar remain visible.
+    #[must_use]
+    pub fn quantize(self, depth: ColorDepth) -> Self {
+        let mut attributes = self.attributes;
+        if depth == ColorDepth::Monochrome && self.bg.is_some() {
+            attributes = attributes.union(Attributes::REVERSE);
+        }
+        Self {
+            fg: self.fg.and_then(|fg| fg.quantize(depth)),
+            bg: self.bg.and_then(|bg| bg.quantize(depth)),
+            attributes,
+        }
+    }
+
+    /// Converts the colors like `quantize`, except that without colors the
+    /// background is dropped instead of replaced by reverse video, so that
+    /// a highlight such as the cursor line does not look like the selection
+    #[must_use]
+    pub fn quantize_highlight(self, depth: ColorDepth) -> Self {
+        if depth == ColorDepth::Monochrome {
+            Self { bg: None, ..self }.quantize(depth)
+        } else {
+            self.quantize(depth)
+        }
+    }
+
+    /// Returns this style with `other` drawn over it: the colors `other`
+    /// sets replace these ones, and the attributes of both apply
+    #[must_use]
+    pub fn layer(self, other: Self) -> Self {
+        Self {
+            fg: other.fg.or(self.fg),
+            bg: other.bg.or(self.bg),
+            attributes: self.attributes.union(other.attributes),
+        }
+    }
+
+    /// Returns the shortest escape sequence switching the terminal from the
+    /// `previous` style to this one. Attributes can only be turned off
+    /// by a reset, so dropping one falls back to the full `escape`.
+    #[must_use]
+    pub fn escape_from(&self, previous: &Self) -> String {
+        if !self.attributes.contains(previous.attributes) {
+            return self.escape();
+        }
+        let mut result = String::new();
+        if self.fg != previous.fg {
+            result.push_str(&self.fg.map_or_else(|| format!("{}", color::Fg(color::Reset)), Color::fg_escape));
+        }
+        if self.bg != previous.bg {
+            result.push_str(&self.bg.map_or_else(|| format!("{}", color::Bg(color::Reset)), Color::bg_escape));
+        }
+        result.push_str(&self.attributes.without(previous.attributes).escape());
+        result
+    }
+
+    /// Returns the escape sequence that resets the terminal style and then
+    /// applies this one
+    #[must_use]
+    pub fn escape(&self) -> String {
+        let mut result = format!("{}", style::Reset);
+        if let Some(fg) = self.fg {
+            result.push_str(&fg.fg_escape());
+        }
+        if let Some(bg) = self.bg {
+            result.push_str(&bg.bg_escape());
+        }
+        result.push_str(&self.attributes.escape());
+        result
+    }
+}
+
+/// A style drawn over the graphemes of a row from `start` up to, but not
+/// including, `end`
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+impl Span {
+    /// Checks if the span covers the grapheme at `index`
+    #[must_use]
+    pub fn contains(&self, index: usize) -> bool {
+        self.start <= index && index < self.end
+    }
+}
+
+/// Parses a `#rrggbb` color
+fn parse_color(hex: &str) -> Result<Color, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid color `{hex}`"));
+    let digits = hex.strip_prefix('#').ok_or_else(invalid)?;
+    if digits.len() != 6 {
+        return Err(invalid());
+    }
+    let component = |range| {
+        digits
+            .get(range)
+            .and_then(|value| u8::from_str_radix(value, 16).ok())
+            .ok_or_else(invalid)
+    };
+    Ok(Color::Rgb(component(0..2)?, component(2..4)?, component(4..6)?))
+}
+
+/// Returns the escape sequence of one of the 16 basic colors, which are
+/// numbered from `base` and, for the bright ones, from `bright_base`
+fn ansi_16_escape(index: u8, base: u8, bright_base: u8) -> String {
+    let code = if index < 8 {
+        base.saturating_add(index)
+    } else {
+        bright_base.saturating_add(index.saturating_sub(8))
+    };
+    format!("\x1b[{code}m")
+}
+
+/// Returns the index of the entry of `palette` closest to `rgb`
+fn nearest(palette: &[(u8, u8, u8)], rgb: (u8, u8, u8)) -> u8 {
+    let mut best = 0;
+    let mut best_distance = u32::MAX;
+    for (index, &entry) in palette.iter().enumerate() {
+        let distance = distance(entry, rgb);
+        if distance < best_distance {
+            best_distance = distance;
+            best = index;
+        }
+    }
+    u8::try_from(best).unwrap_or(0)
+}
+
+/// Returns the index of the closest color of the 256-color palette, either
+/// from the 6x6x6 color cube or from the grayscale ramp
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |component: u8| nearest(&CUBE_LEVELS.map(|level| (level, 0, 0)), (component, 0, 0));
+    let (r_level, g_level, b_level) = (level(r), level(g), level(b));
+    let cube_color = |level: u8| CUBE_LEVELS.get(usize::from(level)).copied().unwrap_or(0);
+    let cube = (cube_color(r_level), cube_color(g_level), cube_color(b_level));
+    let cube_index = r_level
+        .saturating_mul(36)
+        .saturating_add(g_level.saturating_mul(6))
+        .saturating_add(b_level)
+        .saturating_add(16);
+
+    let average = (u16::from(r) + u16::from(g) + u16::from(b)) / 3;
+    let gray_step = u8::try_from(average.saturating_sub(3) / 10).unwrap_or(23).min(23);
+    let gray_level = gray_step.saturating_mul(10).saturating_add(8);
+    let gray_index = gray_step.saturating_add(232);
+
+    if distance((gray_level, gray_level, gray_level), (r, g, b)) < distance(cube, (r, g, b)) {
+        gray_index
+    } else {
+        cube_index
+    }
+}
+
+fn distance(first: (u8, u8, u8), second: (u8, u8, u8)) -> u32 {
+    let component = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+    component(first.0, second.0) + component(first.1, second.1) + component(first.2, second.2)
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse_style() {
+        let parsed = Style::parse("fg=#ff79c6 bg=#282a36 bold underline").expect("valid style");
+        assert_eq!(parsed.fg, Some(Color::Rgb(255, 121, 198)));
+        assert_eq!(parsed.bg, Some(Color::Rgb(40, 42, 54)));
+        assert!(parsed.attributes.contains(Attributes::BOLD));
+        assert!(parsed.attributes.contains(Attributes::UNDERLINE));
+        assert!(!parsed.attributes.contains(Attributes::ITALIC));
+        assert!(Style::parse("fg=#fff").is_err());
+        assert!(Style::parse("blink").is_err());
+    }
+
+    #[test]
+    fn test_quantize() {
+        assert_eq!(Color::Rgb(255, 0, 0).quantize(ColorDepth::Ansi256), Some(Color::Ansi256(196)));
+        assert_eq!(Color::Rgb(128, 128, 128).quantize(ColorDepth::Ansi256), Some(Color::Ansi256(244)));
+        assert_eq!(Color::Rgb(250, 10, 10).quantize(ColorDepth::Ansi16), Some(Color::Ansi16(9)));
+        assert_eq!(Color::Rgb(250, 10, 10).quantize(ColorDepth::Monochrome), None);
+
+        let status_bar = Style::parse("fg=#3f3f3f bg=#efefef").expect("valid style");
+        let monochrome = status_bar.quantize(ColorDepth::Monochrome);
+        assert_eq!(monochrome.fg, None);
+        assert!(monochrome.attributes.contains(Attributes::REVERSE));
+
+        // Without colors, the selection and search matches keep reverse
+        // video but the cursor line must not take it too.
+        let selection = Style::parse("bg=#44475a")
+            .expect("valid style")
+            .quantize(ColorDepth::Monochrome);
+        let search_match = Style::parse("bg=#6e5a1e bold")
+            .expect("valid style")
+            .quantize(ColorDepth::Monochrome);
+        let current_line = Style::parse("bg=#282a36")
+            .expect("valid style")
+            .quantize_highlight(ColorDepth::Monochrome);
+        assert!(selection.attributes.contains(Attributes::REVERSE));
+        assert!(!current_line.attributes.contains(Attributes::REVERSE));
+        assert_ne!(current_line, selection);
+        assert_ne!(current_line, search_match);
+        assert_ne!(selection, search_match);
+        let highlight = Style::parse("bg=#282a36").expect("valid style");
+        assert_eq!(
+            highlight.quantize_highlight(ColorDepth::Ansi256),
+            highlight.quantize(ColorDepth::Ansi256)
+        );
+    }
+
+    #[test]
+    fn test_detect_color_depth() {
+        assert_eq!(ColorDepth::detect_from(Some("truecolor"), Some("xterm")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::detect_from(None, Some("screen-256color")), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::detect_from(None, Some("linux")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::detect_from(None, Some("dumb")), ColorDepth::Monochrome);
+    }
+
+    #[test]
+    fn test_layer_and_escape_from() {
+        let keyword = Style::parse("fg=#ff79c6 bold").expect("valid style");
+        let search_match = Style::parse("bg=#44475a underline").expect("valid style");
+        let layered = keyword.layer(search_match);
+        assert_eq!(layered.fg, keyword.fg);
+        assert_eq!(layered.bg, search_match.bg);
+        assert!(layered.attributes.contains(Attributes::BOLD.union(Attributes::UNDERLINE)));
+
+        // Adding a background and an attribute needs no reset, dropping one
+        // does.
+        assert_eq!(layered.escape_from(&keyword), format!("{}{}", Color::Rgb(68, 71, 90).bg_escape(), style::Underline));
+        assert!(keyword.escape_from(&layered).starts_with(&format!("{}", style::Reset)));
+        assert_eq!(keyword.escape_from(&keyword), "");
+    }
+}
@@ -1,6 +1,9 @@
+use crate::style::Style;
 use crate::Position;
 use std::io::{self, stdout, Error, ErrorKind, Write};
-use termion::color;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
@@ -13,6 +16,9 @@ pub struct Size {
 pub struct Terminal {
     size: Size,
     _stdout: RawTerminal<std::io::Stdout>,
+    /// The keys read by a background thread, so that the editor can wait
+    /// for them and for language servers at the same time
+    keys: Receiver<Result<Key, std::io::Error>>,
 }
 
 impl Terminal {
@@ -24,12 +30,21 @@ impl Terminal {
     pub fn default() -> Result<Self, std::io::Error> {
         if let Ok(size) = termion::terminal_size() {
             if let Ok(raw_terminal) = stdout().into_raw_mode() {
+                let (sender, keys) = mpsc::channel();
+                thread::spawn(move || {
+                    for key in io::stdin().keys() {
+                        if sender.send(key).is_err() {
+                            break;
+                        }
+                    }
+                });
                 Ok(Self {
                     size: Size {
                         width: size.0,
                         height: size.1.saturating_sub(2),
                     },
                     _stdout: raw_terminal,
+                    keys,
                 })
             } else {
                 Err(Error::new(
@@ -80,24 +95,14 @@ impl Terminal {
         print!("{}", termion::clear::CurrentLine);
     }
 
-    /// Sets the background color
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    /// Sets the colors and attributes of the text printed next
+    pub fn set_style(style: &Style) {
+        print!("{}", style.escape());
     }
 
-    /// Resets the background color
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
-    }
-
-    /// Sets the foreground color
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
-    }
-
-    /// Resets the foreground color
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+    /// Resets the colors and attributes to the terminal's defaults
+    pub fn reset_style() {
+        print!("{}", termion::style::Reset);
     }
 
     /// Flush this output stream, ensuring that all intermediately buffered contents reach their destination.
@@ -112,11 +117,23 @@ impl Terminal {
     ///
     /// # Errors
     /// It will fail if not possible to read the keys from the keyboard
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
-            }
+    pub fn read_key(&self) -> Result<Key, std::io::Error> {
+        self.keys.recv().map_err(|_| closed_input())?
+    }
+
+    /// Reads the next key pressed, waiting for it at most `timeout`
+    ///
+    /// # Errors
+    /// It will fail if not possible to read the keys from the keyboard
+    pub fn poll_key(&self, timeout: Duration) -> Result<Option<Key>, std::io::Error> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(key) => key.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(closed_input()),
         }
     }
 }
+
+fn closed_input() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "The keyboard input was closed")
+}
@@ -0,0 +1,258 @@
+use crate::config;
+use crate::highlighting;
+use crate::style::{ColorDepth, Style};
+use crate::Config;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+pub const DEFAULT_THEME: &str = "dracula";
+
+/// Themes shipped with the editor, by name
+const BUILTIN_THEMES: [(&str, &str); 3] = [
+    ("dracula", include_str!("../themes/dracula.ini")),
+    ("gruvbox-dark", include_str!("../themes/gruvbox-dark.ini")),
+    ("solarized-light", include_str!("../themes/solarized-light.ini")),
+];
+
+/// The parts of the screen, other than the text, that a theme styles
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Element {
+    StatusBar,
+    MessageBar,
+    LineNumbers,
+    Selection,
+    CurrentLine,
+    MatchingBracket,
+    Popup,
+    DiagnosticError,
+    DiagnosticWarning,
+    /// Informations and hints from a language server
+    DiagnosticInfo,
+    /// Gutter markers of the lines changed since the last commit
+    GitAdded,
+    GitModified,
+    GitDeleted,
+}
+
+impl Element {
+    const ALL: [Self; 13] = [
+        Self::StatusBar,
+        Self::MessageBar,
+        Self::LineNumbers,
+        Self::Selection,
+        Self::CurrentLine,
+        Self::MatchingBracket,
+        Self::Popup,
+        Self::DiagnosticError,
+        Self::DiagnosticWarning,
+        Self::DiagnosticInfo,
+        Self::GitAdded,
+        Self::GitModified,
+        Self::GitDeleted,
+    ];
+
+    /// Returns the name used for the element in theme files
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::StatusBar => "status_bar",
+            Self::MessageBar => "message_bar",
+            Self::LineNumbers => "line_numbers",
+            Self::Selection => "selection",
+            Self::CurrentLine => "current_line",
+            Self::MatchingBracket => "matching_bracket",
+            Self::Popup => "popup",
+            Self::DiagnosticError => "diagnostic_error",
+            Self::DiagnosticWarning => "diagnostic_warning",
+            Self::DiagnosticInfo => "diagnostic_info",
+            Self::GitAdded => "git_added",
+            Self::GitModified => "git_modified",
+            Self::GitDeleted => "git_deleted",
+        }
+    }
+}
+
+/// Maps every highlighting type and UI element to a `Style`
+pub struct Theme {
+    name: String,
+    syntax: Vec<(highlighting::Type, Style)>,
+    ui: Vec<(Element, Style)>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::load(DEFAULT_THEME).expect("The default theme is built in")
+    }
+}
+
+impl Theme {
+    /// Loads the theme called `name`, looking first for `<name>.ini` in the
+    /// `themes` directory of the user configuration, then at the built-in
+    /// themes
+    ///
+    /// # Errors
+    /// It will return `Err` if there is no theme called `name` or it
+    /// cannot be parsed
+    pub fn load(name: &str) -> Result<Self, Error> {
+        if let Some(path) = user_themes_dir().map(|dir| dir.join(format!("{name}.ini"))) {
+            if path.is_file() {
+                return Self::parse(name, &fs::read_to_string(path)?);
+            }
+        }
+        if let Some(&(_, contents)) = BUILTIN_THEMES
+            .iter()
+            .find(|&&(builtin_name, _)| builtin_name == name)
+        {
+            return Self::parse(name, contents);
+        }
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Unknown theme `{name}`"),
+        ))
+    }
+
+    /// Parses a theme file, where the `[syntax]` section styles each
+    /// highlighting type and the `[ui]` section each screen element.
+    /// Anything left out uses the terminal's default style.
+    ///
+    /// # Errors
+    /// It will return `Err` if the file, an entry name or a style is invalid
+    pub fn parse(name: &str, contents: &str) -> Result<Self, Error> {
+        let theme_config = Config::parse(contents)?;
+        let mut theme = Self {
+            name: name.to_owned(),
+            syntax: highlighting::Type::ALL
+                .iter()
+                .map(|&hl_type| (hl_type, Style::default()))
+                .collect(),
+            ui: Element::ALL
+                .iter()
+                .map(|&element| (element, Style::default()))
+                .collect(),
+        };
+        for section in theme_config.sections("syntax") {
+            for &(ref key, ref value) in section.entries() {
+                let entry = theme
+                    .syntax
+                    .iter_mut()
+                    .find(|&&mut (hl_type, _)| hl_type.name() == key)
+                    .ok_or_else(|| unknown_entry(key))?;
+                entry.1 = Style::parse(value)?;
+            }
+        }
+        for section in theme_config.sections("ui") {
+            for &(ref key, ref value) in section.entries() {
+                let entry = theme
+                    .ui
+                    .iter_mut()
+                    .find(|&&mut (element, _)| element.name() == key)
+                    .ok_or_else(|| unknown_entry(key))?;
+                entry.1 = Style::parse(value)?;
+            }
+        }
+        Ok(theme)
+    }
+
+    /// Converts every color of the theme to the nearest one the terminal
+    /// can display
+    #[must_use]
+    pub fn with_color_depth(mut self, depth: ColorDepth) -> Self {
+        for &mut (_, ref mut style) in self.syntax.iter_mut() {
+            *style = style.quantize(depth);
+        }
+        for &mut (element, ref mut style) in self.ui.iter_mut() {
+            *style = if element == Element::CurrentLine {
+                style.quantize_highlight(depth)
+            } else {
+                style.quantize(depth)
+            };
+        }
+        self
+    }
+
+    /// Returns the name of the theme
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the style of a highlighting type
+    #[must_use]
+    pub fn syntax(&self, hl_type: highlighting::Type) -> Style {
+        self.syntax
+            .iter()
+            .find(|&&(entry_type, _)| entry_type == hl_type)
+            .map(|&(_, style)| style)
+            .unwrap_or_default()
+    }
+
+    /// Returns the style of a screen element
+    #[must_use]
+    pub fn ui(&self, element: Element) -> Style {
+        self.ui
+            .iter()
+            .find(|&&(entry_element, _)| entry_element == element)
+            .map(|&(_, style)| style)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the names of the built-in themes and of the user's themes
+#[must_use]
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_THEMES
+        .iter()
+        .map(|&(name, _)| name.to_owned())
+        .collect();
+    if let Some(entries) = user_themes_dir().and_then(|dir| fs::read_dir(dir).ok()) {
+        for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+            if path.extension().map_or(false, |ext| ext == "ini") {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(stem.to_owned());
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn user_themes_dir() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("themes"))
+}
+
+fn unknown_entry(key: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Unknown theme entry `{key}`"),
+    )
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_builtin_themes_are_complete() {
+        for &(name, contents) in &BUILTIN_THEMES {
+            let theme_config = Config::parse(contents).expect("valid theme file");
+            for hl_type in highlighting::Type::ALL {
+                assert!(
+                    theme_config.get("syntax", hl_type.name()).is_some(),
+                    "{name} does not style {}",
+                    hl_type.name()
+                );
+            }
+            for element in Element::ALL {
+                assert!(
+                    theme_config.get("ui", element.name()).is_some(),
+                    "{name} does not style {}",
+                    element.name()
+                );
+            }
+            assert!(Theme::parse(name, contents).is_ok());
+        }
+    }
+}
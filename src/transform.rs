@@ -0,0 +1,219 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+
+/// How `sort_lines` compares lines
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SortOptions {
+    /// Compare the numbers the lines start with, after any indentation
+    pub numeric: bool,
+    pub ignore_case: bool,
+    pub reverse: bool,
+}
+
+impl SortOptions {
+    /// Parses space separated option names: `numeric`, `nocase` and
+    /// `reverse`
+    ///
+    /// # Errors
+    /// It will return `Err` if an option is unknown
+    pub fn parse<'a>(options: impl IntoIterator<Item = &'a str>) -> Result<Self, Error> {
+        let mut result = Self::default();
+        for option in options {
+            match option {
+                "numeric" => result.numeric = true,
+                "nocase" => result.ignore_case = true,
+                "reverse" => result.reverse = true,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Unknown sort option `{option}`"),
+                    ))
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// The case conversions of `convert_case`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Case {
+    Upper,
+    Lower,
+    Title,
+    Snake,
+    Camel,
+}
+
+impl Case {
+    /// Returns the case with the given name, as typed in the `case` command
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "upper" => Some(Self::Upper),
+            "lower" => Some(Self::Lower),
+            "title" => Some(Self::Title),
+            "snake" | "snake_case" => Some(Self::Snake),
+            "camel" | "CamelCase" => Some(Self::Camel),
+            _ => None,
+        }
+    }
+}
+
+/// Sorts `lines` in place. Lines comparing equal keep their order.
+pub fn sort_lines(lines: &mut [String], options: SortOptions) {
+    lines.sort_by(|first, second| {
+        let ordering = if options.numeric {
+            leading_number(first)
+                .partial_cmp(&leading_number(second))
+                .unwrap_or(Ordering::Equal)
+        } else if options.ignore_case {
+            first.to_lowercase().cmp(&second.to_lowercase())
+        } else {
+            first.cmp(second)
+        };
+        if options.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// Removes the lines that already appeared earlier in `lines`
+pub fn unique_lines(lines: &mut Vec<String>) {
+    let mut seen: HashSet<&str> = HashSet::with_capacity(lines.len());
+    let keep: Vec<bool> = lines.iter().map(|line| seen.insert(line.as_str())).collect();
+    let mut keep = keep.into_iter();
+    lines.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// Converts the case of `text`. Snake and camel case apply to every run of
+/// non-whitespace characters, leaving the whitespace in between as is.
+#[must_use]
+pub fn convert_case(text: &str, case: Case) -> String {
+    match case {
+        Case::Upper => text.to_uppercase(),
+        Case::Lower => text.to_lowercase(),
+        Case::Title | Case::Snake | Case::Camel => {
+            let mut result = String::new();
+            let mut token = String::new();
+            for c in text.chars().chain(std::iter::once(' ')) {
+                if c.is_whitespace() {
+                    result.push_str(&convert_token(&token, case));
+                    token.clear();
+                    result.push(c);
+                } else {
+                    token.push(c);
+                }
+            }
+            result.pop();
+            result
+        }
+    }
+}
+
+/// Converts a token without whitespace to title, snake or camel case
+fn convert_token(token: &str, case: Case) -> String {
+    match case {
+        Case::Snake => split_words(token)
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<String>>()
+            .join("_"),
+        Case::Camel => split_words(token).iter().map(|word| capitalize(word)).collect(),
+        _ => capitalize(token),
+    }
+}
+
+/// Splits an identifier into words at `_` and `-` and at case changes, so
+/// that `parseHTTPRequest` gives `parse`, `HTTP` and `Request`
+fn split_words(token: &str) -> Vec<String> {
+    let chars: Vec<char> = token.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (index, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' {
+            if !word.is_empty() {
+                words.push(word.clone());
+                word.clear();
+            }
+            continue;
+        }
+        let previous = index.checked_sub(1).and_then(|previous| chars.get(previous));
+        let next = chars.get(index.saturating_add(1));
+        let starts_word = c.is_uppercase()
+            && previous.map_or(false, |previous| {
+                previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.map_or(false, |next| next.is_lowercase()))
+            });
+        if starts_word && !word.is_empty() {
+            words.push(word.clone());
+            word.clear();
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Returns `word` with its first letter in upper case and the rest in
+/// lower case
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect()
+    })
+}
+
+/// Returns the number a line starts with after its indentation, or
+/// negative infinity if it does not start with one
+fn leading_number(line: &str) -> f64 {
+    let trimmed = line.trim_start();
+    let end = trimmed
+        .char_indices()
+        .find(|&(index, c)| !(c.is_ascii_digit() || c == '.' || (index == 0 && c == '-')))
+        .map_or(trimmed.len(), |(index, _)| index);
+    trimmed
+        .get(..end)
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|&line| line.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_sort_and_unique() {
+        let mut sorted = lines(&["b", "10 x", "A", "9 y", "a"]);
+        sort_lines(&mut sorted, SortOptions::default());
+        assert_eq!(sorted, lines(&["10 x", "9 y", "A", "a", "b"]));
+        sort_lines(&mut sorted, SortOptions::parse(["numeric"]).expect("valid options"));
+        assert_eq!(sorted, lines(&["A", "a", "b", "9 y", "10 x"]));
+        sort_lines(&mut sorted, SortOptions::parse(["nocase", "reverse"]).expect("valid options"));
+        assert_eq!(sorted, lines(&["b", "A", "a", "9 y", "10 x"]));
+        assert!(SortOptions::parse(["random"]).is_err());
+
+        let mut unique = lines(&["a", "b", "a", "c", "b"]);
+        unique_lines(&mut unique);
+        assert_eq!(unique, lines(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn test_convert_case() {
+        assert_eq!(convert_case("Hello world", Case::Upper), "HELLO WORLD");
+        assert_eq!(convert_case("hELLO wORLD", Case::Title), "Hello World");
+        assert_eq!(convert_case("parseHTTPRequest  MyType", Case::Snake), "parse_http_request  my_type");
+        assert_eq!(convert_case("line_number-width", Case::Camel), "LineNumberWidth");
+        assert_eq!(convert_case("  x", Case::Camel), "  X");
+    }
+}